@@ -0,0 +1 @@
+INSERT IGNORE INTO roles (name) VALUES ('seller'), ('customer');
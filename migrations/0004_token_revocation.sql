@@ -0,0 +1,9 @@
+ALTER TABLE users ADD COLUMN token_version BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE revoked_tokens (
+    jti        CHAR(64) PRIMARY KEY,
+    expires_at DATETIME NOT NULL,
+    INDEX idx_revoked_tokens_expires (expires_at)
+);
+
+INSERT IGNORE INTO roles (name) VALUES ('admin');
@@ -0,0 +1,3 @@
+ALTER TABLE items
+    ADD COLUMN owner_id BIGINT NULL,
+    ADD CONSTRAINT fk_items_owner FOREIGN KEY (owner_id) REFERENCES users(id);
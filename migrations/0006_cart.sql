@@ -0,0 +1,10 @@
+CREATE TABLE cart_items (
+    user_id    BIGINT NOT NULL,
+    item_id    BIGINT NOT NULL,
+    quantity   INT NOT NULL,
+    unit_price DOUBLE NOT NULL,
+    added_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, item_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (item_id) REFERENCES items(id) ON DELETE CASCADE
+);
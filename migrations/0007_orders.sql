@@ -0,0 +1,23 @@
+CREATE TABLE orders (
+    id         BIGINT AUTO_INCREMENT PRIMARY KEY,
+    user_id    BIGINT NOT NULL,
+    status     VARCHAR(20) NOT NULL DEFAULT 'pending',
+    total      DOUBLE NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    INDEX idx_orders_user (user_id)
+);
+
+CREATE TABLE order_items (
+    id         BIGINT AUTO_INCREMENT PRIMARY KEY,
+    order_id   BIGINT NOT NULL,
+    item_id    BIGINT NULL,
+    seller_id  BIGINT NULL,
+    name       VARCHAR(255) NOT NULL,
+    unit_price DOUBLE NOT NULL,
+    quantity   INT NOT NULL,
+    FOREIGN KEY (order_id) REFERENCES orders(id) ON DELETE CASCADE,
+    FOREIGN KEY (item_id) REFERENCES items(id) ON DELETE SET NULL,
+    FOREIGN KEY (seller_id) REFERENCES users(id) ON DELETE SET NULL,
+    INDEX idx_order_items_seller (seller_id)
+);
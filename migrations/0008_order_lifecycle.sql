@@ -0,0 +1,6 @@
+ALTER TABLE orders
+    ADD COLUMN paid_at      DATETIME NULL,
+    ADD COLUMN shipped_at   DATETIME NULL,
+    ADD COLUMN delivered_at DATETIME NULL,
+    ADD COLUMN cancelled_at DATETIME NULL,
+    ADD COLUMN refunded_at  DATETIME NULL;
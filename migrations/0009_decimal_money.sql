@@ -0,0 +1,14 @@
+ALTER TABLE items
+    MODIFY price DECIMAL(19, 4) NOT NULL,
+    ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'EUR' AFTER price;
+
+ALTER TABLE cart_items
+    MODIFY unit_price DECIMAL(19, 4) NOT NULL;
+
+ALTER TABLE orders
+    MODIFY total DECIMAL(19, 4) NOT NULL,
+    ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'EUR' AFTER total;
+
+ALTER TABLE order_items
+    MODIFY unit_price DECIMAL(19, 4) NOT NULL,
+    ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'EUR' AFTER unit_price;
@@ -0,0 +1,7 @@
+ALTER TABLE items
+    ADD COLUMN description TEXT NULL AFTER name;
+
+-- Words shorter than innodb_ft_min_token_size (3 by default) are not
+-- indexed; searches made only of such words fall back to LIKE.
+ALTER TABLE items
+    ADD FULLTEXT INDEX ft_items_name_description (name, description);
@@ -0,0 +1,8 @@
+ALTER TABLE items
+    ADD COLUMN created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD INDEX idx_items_price (price),
+    ADD INDEX idx_items_created_at (created_at);
+
+-- Popularity sorts by units sold.
+ALTER TABLE order_items
+    ADD INDEX idx_order_items_item (item_id);
@@ -0,0 +1,13 @@
+-- Names now only have to be unique among siblings. Existing duplicates,
+-- all top-level so far, get their id appended so the index can be built.
+UPDATE categories c
+    JOIN categories d ON d.name = c.name AND d.id < c.id
+SET c.name = CONCAT(c.name, ' (', c.id, ')');
+
+-- NULLs never collide in a unique index, so top-level categories are keyed
+-- under parent 0 for the sibling check.
+ALTER TABLE categories
+    ADD COLUMN parent_id BIGINT NULL AFTER id,
+    ADD COLUMN sibling_key BIGINT AS (COALESCE(parent_id, 0)) STORED,
+    ADD CONSTRAINT fk_categories_parent FOREIGN KEY (parent_id) REFERENCES categories (id),
+    ADD UNIQUE INDEX uq_categories_sibling_name (sibling_key, name);
@@ -1,83 +1,65 @@
-use crate::models::{LoginRequest, RegisterUser, Role, User, UserResponse};
-use axum::{extract::State, http::StatusCode, Json};
+use crate::models::{
+    ChangePasswordRequest, ChangeRoleRequest, LoginRequest, LoginResponse, LogoutRequest,
+    RefreshRequest, RegisterUser, User, UserResponse,
+};
+use crate::error::{AppError, FieldError, Problem};
+use crate::extract::{Json, OptionalJson, Path};
+use axum::{extract::State, Extension};
+use serde_json::json;
 use bcrypt::{hash, verify};
 use chrono::Utc;
 use jsonwebtoken::{encode, EncodingKey, Header};
-use sqlx::MySqlPool;
-
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String,
-    pub role: String,
-    pub exp: usize,
-}
+use sha2::{Digest, Sha256};
+use crate::auth_middleware::{Claims, ADMIN_ROLE};
+use crate::state::AppState;
 
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    request_body = RegisterUser,
+    responses(
+        (status = 200, description = "The new user", body = UserResponse),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn register_user(
-    State(pool): State<MySqlPool>,
+    State(state): State<AppState>,
     Json(data): Json<RegisterUser>,
-) -> Result<Json<UserResponse>, StatusCode> {
+) -> Result<Json<UserResponse>, AppError> {
     tracing::info!("Registering new user: {}", data.username);
 
-    let role = sqlx::query_as!(
-        Role,
-        r#"SELECT id, name FROM roles WHERE name = ?"#,
-        data.role
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("DB error (role check): {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let role = state.users.find_role(&data.role).await?;
 
     let role = match role {
-        Some(r) => r,
-        None => {
+        Some(r) if r.name != ADMIN_ROLE => r,
+        _ => {
             tracing::warn!("Invalid role: {}", data.role);
-            return Err(StatusCode::BAD_REQUEST);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "role",
+                format!("unknown role '{}'", data.role),
+            )]));
         }
     };
 
-    let password_hash = hash(&data.password, 10).map_err(|e| {
+    let password_hash = hash(&data.password, state.config.auth.bcrypt_cost).map_err(|e| {
         tracing::error!("Password hashing failed: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        AppError::Internal("Password hashing failed.".into())
     })?;
 
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO users (username, password_hash, role_id)
-        VALUES (?, ?, ?)
-        "#,
-        data.username,
-        password_hash,
-        role.id
-    )
-    .execute(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Insert failed: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let user_id = result.last_insert_id() as i64;
-
-    let user = sqlx::query_as!(
-        User,
-        r#"
-        SELECT id, username, password_hash, role_id
-        FROM users
-        WHERE id = ?
-        "#,
-        user_id
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Fetch inserted user failed: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let user = state
+        .users
+        .create(&data.username, &password_hash, role.id)
+        .await
+        .map_err(|e| match e {
+            AppError::Conflict(..) => AppError::Conflict(
+                "username_taken",
+                format!("Username '{}' is already taken.", data.username),
+            ),
+            other => other,
+        })?;
 
     let response = UserResponse {
         id: user.id,
@@ -88,74 +70,355 @@ pub async fn register_user(
     Ok(Json(response))
 }
 
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Access and refresh token", body = LoginResponse),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn login_user(
-    State(pool): State<MySqlPool>,
+    State(state): State<AppState>,
     Json(data): Json<LoginRequest>,
-) -> Result<Json<String>, StatusCode> {
+) -> Result<Json<LoginResponse>, AppError> {
     tracing::info!("Login attempt for {}", data.username);
 
-    let user = sqlx::query_as!(
-        User,
-        r#"
-        SELECT id, username, password_hash, role_id
-        FROM users
-        WHERE username = ?
-        "#,
-        data.username
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("DB error: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let user = state.users.find_by_username(&data.username).await?;
 
     let user = match user {
         Some(u) => u,
         None => {
             tracing::warn!("User not found: {}", data.username);
-            return Err(StatusCode::UNAUTHORIZED);
+            state.metrics.login(false);
+            return Err(AppError::Unauthorized("Invalid username or password.".into()));
         }
     };
 
     let is_valid = verify(&data.password, &user.password_hash)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|e| {
+            tracing::error!("Password verification failed: {:?}", e);
+            AppError::Internal("Password verification failed.".into())
+        })?;
 
     if !is_valid {
         tracing::warn!("Invalid password for user: {}", data.username);
-        return Err(StatusCode::UNAUTHORIZED);
+        state.metrics.login(false);
+        return Err(AppError::Unauthorized("Invalid username or password.".into()));
     }
 
+    let role = state.users.role_name(user.role_id).await?;
+
+    let family_id = generate_token();
+
+    let response = issue_tokens(&state, &user, role, &family_id).await?;
+
+    state.metrics.login(true);
+
+    Ok(Json(response))
+}
+
+/// Exchanges a refresh token for a new access/refresh pair. Every refresh
+/// token is single use; presenting one that was already rotated revokes
+/// the whole family, since either the client or an attacker holds a copy.
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "A new token pair", body = LoginResponse),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn refresh_token(
+    State(state): State<AppState>,
+    Json(data): Json<RefreshRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let token_hash = hash_token(&data.refresh_token);
+
+    let stored = state.users.find_refresh_token(&token_hash).await?;
+
+    let stored = match stored {
+        Some(t) => t,
+        None => {
+            tracing::warn!("Unknown refresh token presented");
+            return Err(AppError::Unauthorized("Invalid refresh token.".into()));
+        }
+    };
+
+    if stored.used_at.is_some() || stored.revoked_at.is_some() {
+        tracing::warn!(
+            "Refresh token reuse detected for user {}, revoking family {}",
+            stored.user_id,
+            stored.family_id
+        );
+        state.users.revoke_family(&stored.family_id).await?;
+        return Err(AppError::Unauthorized("Invalid refresh token.".into()));
+    }
+
+    if stored.expires_at < Utc::now().naive_utc() {
+        tracing::warn!("Expired refresh token for user {}", stored.user_id);
+        return Err(AppError::Unauthorized("Refresh token has expired.".into()));
+    }
+
+    // Of two concurrent refreshes with the same token only one can mark it.
+    let marked = state.users.use_refresh_token(stored.id).await?;
+
+    if !marked {
+        tracing::warn!("Concurrent refresh token reuse for family {}", stored.family_id);
+        state.users.revoke_family(&stored.family_id).await?;
+        return Err(AppError::Unauthorized("Invalid refresh token.".into()));
+    }
+
+    let user = state.users.get(stored.user_id).await?;
+
+    let user = match user {
+        Some(u) => u,
+        None => {
+            tracing::warn!("Refresh token for deleted user {}", stored.user_id);
+            return Err(AppError::Unauthorized("Invalid refresh token.".into()));
+        }
+    };
+
+    let role = state.users.role_name(user.role_id).await?;
+
+    tracing::info!("Rotating refresh token for {}", user.username);
+
+    let response = issue_tokens(&state, &user, role, &stored.family_id).await?;
+
+    Ok(Json(response))
+}
+
+/// Signs a new access token and stores a new refresh token in `family_id`.
+async fn issue_tokens(
+    state: &AppState,
+    user: &User,
+    role: String,
+    family_id: &str,
+) -> Result<LoginResponse, AppError> {
+    let auth = &state.config.auth;
+
     let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::minutes(20))
+        .checked_add_signed(chrono::Duration::minutes(auth.access_token_minutes))
         .expect("valid timestamp")
         .timestamp() as usize;
 
-    let role = sqlx::query_scalar!(r#"SELECT name FROM roles WHERE id = ?"#, user.role_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to get role name: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
     let claims = Claims {
         sub: user.username.clone(),
+        uid: user.id,
         role, // sada je ovo npr. "seller" ili "customer"
         exp: expiration,
+        jti: generate_token(),
+        ver: user.token_version,
     };
 
-    let secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "secret".into());
-
-    let token = encode(
+    let access_token = encode(
         &Header::default(),
         &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
+        &EncodingKey::from_secret(auth.jwt_secret.as_ref()),
     )
     .map_err(|e| {
         tracing::error!("Token generation failed: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+        AppError::Internal("Token generation failed.".into())
     })?;
 
-    Ok(Json(token))
+    let refresh_token = generate_token();
+    let refresh_hash = hash_token(&refresh_token);
+    let refresh_expires_at = Utc::now().naive_utc() + chrono::Duration::days(auth.refresh_token_days);
+
+    state
+        .users
+        .store_refresh_token(user.id, family_id, &refresh_hash, refresh_expires_at)
+        .await?;
+
+    Ok(LoginResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".into(),
+        expires_in: auth.access_token_minutes * 60,
+    })
+}
+
+/// Deny-lists the presented access token and, if given, revokes the refresh
+/// token's family. With `everywhere` every token of the user is revoked.
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    request_body = Option<LogoutRequest>,
+    responses(
+        (status = 200, description = "Tokens revoked", body = serde_json::Value, example = json!({ "message": "..." })),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn logout_user(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    OptionalJson(data): OptionalJson<LogoutRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    tracing::info!("Logout for {}", claims.sub);
+
+    let data = data.unwrap_or_default();
+
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp as i64, 0)
+        .map(|t| t.naive_utc())
+        .unwrap_or_else(|| Utc::now().naive_utc());
+
+    state.users.deny_access_token(&claims.jti, expires_at).await?;
+
+    if let Some(refresh_token) = data.refresh_token {
+        let token_hash = hash_token(&refresh_token);
+
+        let family_id = state
+            .users
+            .find_refresh_token(&token_hash)
+            .await?
+            .filter(|t| t.user_id == claims.uid)
+            .map(|t| t.family_id);
+
+        if let Some(family_id) = family_id {
+            state.users.revoke_family(&family_id).await?;
+        }
+    }
+
+    if data.everywhere {
+        revoke_user_tokens(&state, claims.uid).await?;
+    }
+
+    Ok(Json(json!({ "message": "Logged out." })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password",
+    tag = "auth",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed, every token revoked", body = serde_json::Value, example = json!({ "message": "..." })),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(data): Json<ChangePasswordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    tracing::info!("Password change for {}", claims.sub);
+
+    let user = state
+        .users
+        .get(claims.uid)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found.", claims.uid)))?;
+
+    let is_valid = verify(&data.current_password, &user.password_hash)
+        .map_err(|e| {
+            tracing::error!("Password verification failed: {:?}", e);
+            AppError::Internal("Password verification failed.".into())
+        })?;
+
+    if !is_valid {
+        tracing::warn!("Invalid current password for user: {}", claims.sub);
+        return Err(AppError::Unauthorized("Current password is incorrect.".into()));
+    }
+
+    let password_hash = hash(&data.new_password, state.config.auth.bcrypt_cost).map_err(|e| {
+        tracing::error!("Password hashing failed: {:?}", e);
+        AppError::Internal("Password hashing failed.".into())
+    })?;
+
+    state.users.set_password(user.id, &password_hash).await?;
+
+    revoke_user_tokens(&state, user.id).await?;
+
+    Ok(Json(json!({ "message": "Password changed, please log in again." })))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/users/{id}/role",
+    tag = "admin",
+    params(("id" = i64, Path, description = "User id")),
+    request_body = ChangeRoleRequest,
+    responses(
+        (status = 200, description = "The user with the new role", body = UserResponse),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn change_user_role(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Json(data): Json<ChangeRoleRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    tracing::info!("PATCH /users/{}/role: {}", id, data.role);
+
+    let role = state.users.find_role(&data.role).await?;
+
+    let role = match role {
+        Some(r) => r,
+        None => {
+            tracing::warn!("Invalid role: {}", data.role);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "role",
+                format!("unknown role '{}'", data.role),
+            )]));
+        }
+    };
+
+    let updated = state.users.set_role(id, role.id).await?;
+
+    if !updated {
+        tracing::warn!("User {} not found for role change", id);
+        return Err(AppError::NotFound(format!("User {} not found.", id)));
+    }
+
+    revoke_user_tokens(&state, id).await?;
+
+    let user = state
+        .users
+        .get(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("User {} not found.", id)))?;
+
+    Ok(Json(UserResponse {
+        id: user.id,
+        username: user.username,
+        role_id: user.role_id,
+    }))
+}
+
+/// Invalidates every access and refresh token of a user by bumping its
+/// token version and revoking all of its refresh tokens.
+async fn revoke_user_tokens(state: &AppState, user_id: i64) -> Result<(), AppError> {
+    tracing::info!("Revoking all tokens of user {}", user_id);
+
+    state.users.revoke_user_tokens(user_id).await
+}
+
+/// 32 random bytes, hex encoded. Used for refresh tokens and family ids.
+fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+/// Refresh tokens are stored as SHA-256 hashes so a database leak does not
+/// hand out usable tokens.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
@@ -1,60 +1,106 @@
+use crate::error::AppError;
+use crate::request_id::RequestSpan;
+use crate::state::AppState;
 use axum::{
     body::Body,
-    extract::Request,
-    http::StatusCode,
+    extract::{Request, State},
     middleware::Next,
     response::Response,
 };
 use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm};
-use serde::Deserialize;
-use std::{env, future::Future, pin::Pin};
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin};
 use axum::http::HeaderMap;
 
-#[derive(Debug, Deserialize, Clone)]
+/// Role that passes every `require_role` check.
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
+    /// Id of the user in `sub`.
+    pub uid: i64,
     pub role: String,
     pub exp: usize,
+    /// Unique token id, used to deny-list a single token on logout.
+    pub jti: String,
+    /// The user's `token_version` when the token was issued. Bumping the
+    /// version revokes every token issued before.
+    pub ver: i64,
 }
 
+/// Verifies the Bearer token and checks it against the database: the token
+/// must not be logged out, the user must still exist and its token version
+/// and role must match the claims.
+#[tracing::instrument(skip_all)]
 pub async fn require_auth(
+    State(state): State<AppState>,
     mut req: Request<Body>,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, AppError> {
     let headers: &HeaderMap = req.headers();
     let auth_header = headers
         .get("Authorization")
         .and_then(|h| h.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
-
-    let token = auth_header.strip_prefix("Bearer ").ok_or(StatusCode::UNAUTHORIZED)?;
+        .ok_or_else(|| AppError::Unauthorized("Missing Authorization header.".into()))?;
 
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".into());
+    let token = auth_header
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| AppError::Unauthorized("Expected a Bearer token.".into()))?;
 
     let decoded = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(secret.as_ref()),
+        &DecodingKey::from_secret(state.config.auth.jwt_secret.as_ref()),
         &Validation::new(Algorithm::HS256),
     )
-        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+        .map_err(|_| AppError::Unauthorized("Invalid or expired token.".into()))?;
+
+    let claims = decoded.claims;
+
+    let current = state.users.token_state(claims.uid, &claims.jti).await?;
+
+    let current = match current {
+        Some(c) => c,
+        None => {
+            tracing::warn!("Token for unknown user {}", claims.sub);
+            return Err(AppError::Unauthorized("Invalid or expired token.".into()));
+        }
+    };
 
-    req.extensions_mut().insert(decoded.claims);
+    if current.revoked || current.token_version != claims.ver || current.role != claims.role {
+        tracing::warn!("Revoked token presented by {}", claims.sub);
+        return Err(AppError::Unauthorized("Token has been revoked.".into()));
+    }
+
+    // `Span::current()` is this middleware's own span, not the request's.
+    if let Some(RequestSpan(span)) = req.extensions().get::<RequestSpan>() {
+        span.record("user", claims.sub.as_str());
+    }
+
+    req.extensions_mut().insert(claims);
 
     Ok(next.run(req).await)
 }
 
+type RoleCheck = Pin<Box<dyn Future<Output = Result<Response, AppError>> + Send>>;
+
 pub fn require_role(
     role_required: &'static str,
-) -> impl Fn(Request<Body>, Next) -> Pin<Box<dyn Future<Output = Result<Response, StatusCode>> + Send>> + Clone + Send + 'static {
+) -> impl Fn(Request<Body>, Next) -> RoleCheck + Clone + Send + 'static {
     move |req: Request<Body>, next: Next| {
         let role_required = role_required.to_string();
         Box::pin(async move {
             let claims = req.extensions().get::<Claims>();
 
             match claims {
-                Some(c) if c.role == role_required => Ok(next.run(req).await),
-                Some(_) => Err(StatusCode::FORBIDDEN),
-                None => Err(StatusCode::UNAUTHORIZED),
+                Some(c) if c.role == role_required || c.role == ADMIN_ROLE => {
+                    Ok(next.run(req).await)
+                }
+                Some(_) => Err(AppError::Forbidden(format!(
+                    "This action requires the '{}' role.",
+                    role_required
+                ))),
+                None => Err(AppError::Unauthorized("Authentication required.".into())),
             }
         })
     }
@@ -0,0 +1,302 @@
+use crate::auth_middleware::Claims;
+use crate::error::{AppError, FieldError, Problem};
+use crate::models::{AddCartItem, CartLine, CartResponse, UpdateCartItem};
+use crate::money::Money;
+use crate::state::AppState;
+use crate::extract::{Json, Path};
+use axum::{extract::State, Extension};
+
+#[utoipa::path(
+    get,
+    path = "/cart",
+    tag = "cart",
+    responses(
+        (status = 200, description = "The cart", body = CartResponse),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_cart(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<CartResponse>, AppError> {
+    tracing::info!("GET /cart for {}", claims.sub);
+
+    let cart = load_cart(&state, claims.uid).await?;
+
+    Ok(Json(cart))
+}
+
+#[utoipa::path(
+    post,
+    path = "/cart/items",
+    tag = "cart",
+    request_body = AddCartItem,
+    responses(
+        (status = 200, description = "The updated cart", body = CartResponse),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn add_cart_item(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<AddCartItem>,
+) -> Result<Json<CartResponse>, AppError> {
+    tracing::info!("POST /cart/items for {}: {:?}", claims.sub, payload);
+
+    if payload.quantity < 1 {
+        tracing::warn!("Invalid cart quantity: {}", payload.quantity);
+        return Err(AppError::Validation(vec![FieldError::new(
+            "quantity",
+            "must be at least 1",
+        )]));
+    }
+
+    let item = state.items.get(payload.item_id).await?;
+
+    let item = match item {
+        Some(i) => i,
+        None => {
+            tracing::warn!("Item {} not found for cart", payload.item_id);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "item_id",
+                format!("item {} does not exist", payload.item_id),
+            )]));
+        }
+    };
+
+    // A cart is paid in one currency, so its total stays meaningful.
+    let cart_currency = state.carts.currency(claims.uid, payload.item_id).await?;
+
+    if let Some(currency) = cart_currency {
+        if currency != item.price.currency {
+            tracing::warn!(
+                "Item {} is priced in {}, cart of {} is in {}",
+                payload.item_id,
+                item.price.currency,
+                claims.sub,
+                currency
+            );
+            return Err(AppError::Conflict(
+                "currency_mismatch",
+                format!(
+                    "Item {} is priced in {} but the cart is in {}.",
+                    payload.item_id, item.price.currency, currency
+                ),
+            ));
+        }
+    }
+
+    let in_cart = state
+        .carts
+        .quantity(claims.uid, payload.item_id)
+        .await?
+        .unwrap_or(0);
+
+    let requested = match in_cart.checked_add(payload.quantity) {
+        Some(requested) => requested,
+        None => {
+            tracing::warn!("Cart quantity of item {} overflows for {}", payload.item_id, claims.sub);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "quantity",
+                "is too large",
+            )]));
+        }
+    };
+    ensure_available(payload.item_id, requested, item.quantity)?;
+
+    state
+        .carts
+        .put(claims.uid, payload.item_id, requested, item.price.amount)
+        .await?;
+
+    let cart = load_cart(&state, claims.uid).await?;
+
+    Ok(Json(cart))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/cart/items/{item_id}",
+    tag = "cart",
+    params(("item_id" = i64, Path, description = "Item id")),
+    request_body = UpdateCartItem,
+    responses(
+        (status = 200, description = "The updated cart", body = CartResponse),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn update_cart_item(
+    Path(item_id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<UpdateCartItem>,
+) -> Result<Json<CartResponse>, AppError> {
+    tracing::info!("PATCH /cart/items/{} for {}: {:?}", item_id, claims.sub, payload);
+
+    if payload.quantity < 1 {
+        tracing::warn!("Invalid cart quantity: {}", payload.quantity);
+        return Err(AppError::Validation(vec![FieldError::new(
+            "quantity",
+            "must be at least 1",
+        )]));
+    }
+
+    let in_cart = state.carts.quantity(claims.uid, item_id).await?;
+    let line = match in_cart {
+        Some(_) => state.items.get(item_id).await?,
+        None => None,
+    };
+
+    let line = match line {
+        Some(l) => l,
+        None => {
+            tracing::warn!("Item {} is not in the cart of {}", item_id, claims.sub);
+            return Err(AppError::NotFound(format!("Item {} is not in the cart.", item_id)));
+        }
+    };
+
+    ensure_available(item_id, payload.quantity, line.quantity)?;
+
+    state.carts.set_quantity(claims.uid, item_id, payload.quantity).await?;
+
+    let cart = load_cart(&state, claims.uid).await?;
+
+    Ok(Json(cart))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/cart/items/{item_id}",
+    tag = "cart",
+    params(("item_id" = i64, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "The updated cart", body = CartResponse),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn remove_cart_item(
+    Path(item_id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<CartResponse>, AppError> {
+    tracing::info!("DELETE /cart/items/{} for {}", item_id, claims.sub);
+
+    let removed = state.carts.remove(claims.uid, item_id).await?;
+
+    if !removed {
+        tracing::warn!("Item {} is not in the cart of {}", item_id, claims.sub);
+        return Err(AppError::NotFound(format!("Item {} is not in the cart.", item_id)));
+    }
+
+    let cart = load_cart(&state, claims.uid).await?;
+
+    Ok(Json(cart))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/cart",
+    tag = "cart",
+    responses(
+        (status = 200, description = "The empty cart", body = CartResponse),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn clear_cart(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<CartResponse>, AppError> {
+    tracing::info!("DELETE /cart for {}", claims.sub);
+
+    state.carts.clear(claims.uid).await?;
+
+    Ok(Json(CartResponse {
+        lines: Vec::new(),
+        total: None,
+    }))
+}
+
+fn ensure_available(item_id: i64, requested: i32, available: i32) -> Result<(), AppError> {
+    if requested > available {
+        tracing::warn!(
+            "Requested {} of item {}, only {} available",
+            requested,
+            item_id,
+            available
+        );
+        return Err(AppError::Conflict(
+            "insufficient_stock",
+            format!(
+                "Only {} of item {} available, {} requested.",
+                available, item_id, requested
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Loads the cart priced at the current item prices. Lines whose price
+/// changed since the last time the cart was shown are flagged once, then
+/// the stored price is brought up to date.
+async fn load_cart(state: &AppState, user_id: i64) -> Result<CartResponse, AppError> {
+    let rows = state.carts.lines(user_id).await?;
+
+    let repriced = rows.iter().any(|r| r.stored_price != r.current_price);
+
+    if repriced {
+        tracing::info!("Repricing cart of user {}", user_id);
+
+        state.carts.reprice(user_id).await?;
+    }
+
+    let lines: Vec<CartLine> = rows
+        .into_iter()
+        .map(|r| {
+            let price_changed = r.stored_price != r.current_price;
+            let unit_price = Money::new(r.current_price, r.currency);
+
+            // Prices fit DECIMAL(19,4) and quantities an i32, so the product
+            // cannot overflow a Decimal.
+            let line_total = unit_price.times(r.quantity).ok_or_else(|| {
+                AppError::Internal(format!("Cart line total of item {} overflows.", r.item_id))
+            })?;
+
+            Ok(CartLine {
+                item_id: r.item_id,
+                name: r.name,
+                unit_price,
+                quantity: r.quantity,
+                line_total,
+                available: r.available,
+                price_changed,
+                previous_price: price_changed.then(|| Money::new(r.stored_price, r.currency)),
+            })
+        })
+        .collect::<Result<_, AppError>>()?;
+
+    let total = Money::sum(lines.iter().map(|l| &l.line_total));
+
+    Ok(CartResponse { lines, total })
+}
@@ -0,0 +1,172 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A single field that failed validation.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Error returned by every handler and middleware. Rendered as an
+/// RFC 7807 `application/problem+json` document.
+#[derive(Debug)]
+pub enum AppError {
+    Validation(Vec<FieldError>),
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// The request clashes with the current state; the first field is the
+    /// machine-readable code.
+    Conflict(&'static str, String),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) | AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(..) => StatusCode::CONFLICT,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(code, _) => code,
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            AppError::Validation(_) => "One or more fields are invalid.",
+            AppError::BadRequest(m)
+            | AppError::Unauthorized(m)
+            | AppError::Forbidden(m)
+            | AppError::NotFound(m)
+            | AppError::Conflict(_, m)
+            | AppError::Internal(m) => m,
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => AppError::NotFound("Resource not found.".into()),
+            sqlx::Error::Database(db) if db.is_unique_violation() => AppError::Conflict(
+                "duplicate",
+                "A record with the same unique value already exists.".into(),
+            ),
+            sqlx::Error::Database(db) if db.is_foreign_key_violation() => AppError::Conflict(
+                "constraint_violation",
+                "The request conflicts with related records.".into(),
+            ),
+            _ => AppError::Internal("Database error.".into()),
+        }
+    }
+}
+
+// Rejections of the extractors in `crate::extract`. Their text says what
+// was wrong, e.g. "Failed to deserialize the JSON body into the target
+// type: quantity: invalid type: string \"2\", expected i32".
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        tracing::warn!("Rejected JSON body: {}", rejection.body_text());
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        tracing::warn!("Rejected query string: {}", rejection.body_text());
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection {
+            PathRejection::FailedToDeserializePathParams(e) => {
+                tracing::warn!("Rejected path: {}", e.body_text());
+                AppError::BadRequest(e.body_text())
+            }
+            // A route without the parameters its handler expects.
+            other => {
+                tracing::error!("Path extraction failed: {}", other.body_text());
+                AppError::Internal("Path extraction failed.".into())
+            }
+        }
+    }
+}
+
+/// Body of every error response, as documented in the OpenAPI spec.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    pub kind: &'static str,
+    #[schema(example = "Not Found")]
+    pub title: &'static str,
+    #[schema(example = 404)]
+    pub status: u16,
+    /// Machine-readable error code, e.g. `not_found` or `insufficient_stock`.
+    #[schema(example = "not_found")]
+    pub code: &'static str,
+    pub detail: String,
+    /// Only for `validation_failed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        let mut body = Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            code: self.code(),
+            detail: self.message().to_string(),
+            errors: None,
+        };
+
+        if let AppError::Validation(errors) = self {
+            body.errors = Some(errors);
+        }
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(body),
+        )
+            .into_response()
+    }
+}
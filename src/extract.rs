@@ -0,0 +1,63 @@
+//! Stand-ins for axum's `Json`, `Query` and `Path` extractors whose
+//! rejections are [`AppError`]s, so a malformed body, query string or path
+//! is answered with a problem document like every other error.
+
+use crate::error::AppError;
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, FromRequestParts, Request},
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+#[derive(Debug, Clone, Copy, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+/// A JSON body that may be left out. Only an empty body counts as absent;
+/// anything else has to be valid JSON of the expected shape, so a typo is
+/// an error rather than silently taken for "no body".
+#[derive(Debug, Clone, Default)]
+pub struct OptionalJson<T>(pub Option<T>);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for OptionalJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+        let bytes = Bytes::from_request(req, state).await.map_err(|rejection| {
+            tracing::warn!("Rejected body: {}", rejection.body_text());
+            AppError::BadRequest(rejection.body_text())
+        })?;
+
+        if bytes.is_empty() {
+            return Ok(OptionalJson(None));
+        }
+
+        let mut req = Request::new(Body::from(bytes));
+        *req.headers_mut() = headers;
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+
+        Ok(OptionalJson(Some(value)))
+    }
+}
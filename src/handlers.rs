@@ -1,469 +1,940 @@
-use crate::models::{CreateItem, Item, Category, ItemQuery};
+use crate::models::{
+    Category, CategoryItemsQuery, CategoryNode, CreateCategory, CreateItem, Item, ItemListQuery,
+    ItemListing, ItemQuery, ItemSort, UpdateCategory, UpdateItem,
+};
+use crate::money::Money;
+use crate::auth_middleware::{Claims, ADMIN_ROLE};
+use crate::error::{AppError, FieldError, Problem};
+use crate::pagination::{self, Page, PageQuery};
+use crate::repo::{ItemFilter, ItemOrder, NewItem};
+use crate::state::AppState;
+use crate::extract::{Json, Path, Query};
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    Json,
+    extract::{OriginalUri, State},
+    http::{HeaderMap, Uri},
+    Extension,
 };
 use serde_json::json;
-use sqlx::MySqlPool;
-use axum::extract::Query;
-
-pub async fn get_all_items(State(pool): State<MySqlPool>) -> Result<Json<Vec<Item>>, StatusCode> {
-    tracing::info!("Retrieving all items from database...");
-
-    let items = sqlx::query_as!(
-        Item,
-        r#"SELECT id, name, price, quantity, category_id FROM items"#
-    )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Database error: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    Ok(Json(items))
+
+/// Longest item description, in characters.
+const MAX_DESCRIPTION_LEN: usize = 5000;
+
+/// Validates the item fields that are present; `None` means "not provided".
+fn validate_item_fields(
+    name: Option<&str>,
+    description: Option<&str>,
+    price: Option<&Money>,
+    quantity: Option<i32>,
+) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if let Some(name) = name {
+        if name.trim().len() < 3 {
+            errors.push(FieldError::new("name", "must be at least 3 characters"));
+        }
+    }
+
+    if let Some(description) = description {
+        if description.chars().count() > MAX_DESCRIPTION_LEN {
+            errors.push(FieldError::new(
+                "description",
+                format!("must be at most {} characters", MAX_DESCRIPTION_LEN),
+            ));
+        }
+    }
+
+    if let Some(price) = price {
+        if let Err(message) = price.validate() {
+            errors.push(FieldError::new("price", message));
+        }
+    }
+
+    if let Some(quantity) = quantity {
+        if quantity < 1 {
+            errors.push(FieldError::new("quantity", "must be at least 1"));
+        }
+    }
+
+    errors
+}
+
+/// Items can only be changed by the seller who created them or by an admin.
+/// Items without an owner predate ownership tracking and are admin-only.
+fn ensure_owner(claims: &Claims, item: &Item) -> Result<(), AppError> {
+    if claims.role == ADMIN_ROLE || item.owner_id == Some(claims.uid) {
+        return Ok(());
+    }
+
+    tracing::warn!("User {} is not the owner of item {}", claims.sub, item.id);
+    Err(AppError::Forbidden(format!(
+        "Item {} belongs to another seller.",
+        item.id
+    )))
+}
+
+/// Adds a `category_id` field error when the referenced category does not exist.
+async fn check_category(
+    state: &AppState,
+    category_id: Option<i64>,
+    errors: &mut Vec<FieldError>,
+) -> Result<(), AppError> {
+    let Some(cat_id) = category_id else {
+        return Ok(());
+    };
+
+    let category = state.categories.get(cat_id).await?;
+
+    if category.is_none() {
+        errors.push(FieldError::new(
+            "category_id",
+            format!("category {} does not exist", cat_id),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Lists items with optional search, filters and sorting, plus the facet
+/// counts a storefront needs for its filter sidebar.
+#[utoipa::path(
+    get,
+    path = "/items",
+    tag = "items",
+    params(ItemListQuery, PageQuery),
+    responses(
+        (status = 200, description = "One page of matching items and facet counts", body = ItemListing),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_all_items(
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<ItemListQuery>,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<ItemListing>), AppError> {
+    tracing::info!("GET /items: {:?}", params);
+
+    let mut errors = Vec::new();
+
+    if let Some(q) = &params.q {
+        if q.trim().len() < 2 {
+            errors.push(FieldError::new("q", "must be at least 2 characters"));
+        }
+    }
+
+    if params.min_price.is_some_and(|min| min.is_sign_negative()) {
+        errors.push(FieldError::new("min_price", "must not be negative"));
+    }
+
+    if params.max_price.is_some_and(|max| max.is_sign_negative()) {
+        errors.push(FieldError::new("max_price", "must not be negative"));
+    }
+
+    // Amounts in different currencies do not compare.
+    let by_price = params.min_price.is_some()
+        || params.max_price.is_some()
+        || params.sort == Some(ItemSort::Price);
+    if by_price && params.currency.is_none() {
+        errors.push(FieldError::new("currency", "is required to filter or sort by price"));
+    }
+
+    if let (Some(min), Some(max)) = (params.min_price, params.max_price) {
+        if min > max {
+            errors.push(FieldError::new("max_price", "must not be below min_price"));
+        }
+    }
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid item listing query: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    let page = paging.resolve(&state.config.pagination)?;
+
+    let sort = params
+        .sort
+        .map(|key| (key, params.order.unwrap_or(key.default_order())));
+
+    let category_ids = if params.include_descendants {
+        with_descendants(&state, params.category).await?
+    } else {
+        params.category
+    };
+
+    let filter = ItemFilter {
+        search: params.q,
+        min_price: params.min_price,
+        max_price: params.max_price,
+        currency: params.currency,
+        in_stock: params.in_stock,
+        category_ids,
+        owner_id: None,
+    };
+
+    let page = state
+        .items
+        .browse(&filter, ItemOrder::resolve(&filter, sort), &page)
+        .await?;
+    let facets = state.items.facets(&filter).await?;
+
+    Ok((
+        pagination::links(&uri, page.next_cursor.as_deref()),
+        Json(ItemListing {
+            items: page.items,
+            total: page.total,
+            next_cursor: page.next_cursor,
+            facets,
+        }),
+    ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = i64, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "The item", body = Item),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_item(
     Path(id): Path<i64>,
-    State(pool): State<MySqlPool>,
-) -> Result<Json<Item>, StatusCode> {
+    State(state): State<AppState>,
+) -> Result<Json<Item>, AppError> {
     tracing::info!("GET /items/{}", id);
 
-    let item = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE id = ?
-        "#,
-        id
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("DB error: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let item = state.items.get(id).await?;
 
     match item {
         Some(i) => Ok(Json(i)),
         None => {
             tracing::error!("Item with id {} is not found", id);
-            Err(StatusCode::NOT_FOUND)
+            Err(AppError::NotFound(format!("Item {} not found.", id)))
         }
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = i64, Path, description = "Item id")),
+    request_body = CreateItem,
+    responses(
+        (status = 200, description = "The updated item", body = Item),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn update_item(
     Path(id): Path<i64>,
-    State(pool): State<MySqlPool>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(payload): Json<CreateItem>,
-) -> Result<Json<Item>, StatusCode> {
+) -> Result<Json<Item>, AppError> {
     tracing::info!("POST /items/{}", id);
 
-    let existing = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE id = ?
-        "#,
-        id
-    )
-        .fetch_optional(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("DB error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let _existing = match existing {
+    let existing = state.items.get(id).await?;
+
+    let existing = match existing {
         Some(item) => item,
         None => {
             tracing::warn!("Item {} not found for update", id);
-            return Err(StatusCode::NOT_FOUND);
+            return Err(AppError::NotFound(format!("Item {} not found.", id)));
         }
     };
 
-    if payload.name.trim().len() < 3 {
-        tracing::warn!("Invalid name: {}", payload.name);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if payload.price < 0.0 {
-        tracing::warn!("Invalid price: {}", payload.price);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if payload.quantity < 1 {
-        tracing::warn!("Invalid quantity: {}", payload.quantity);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if let Some(cat_id) = payload.category_id {
-        let category_exists = sqlx::query_scalar!(
-        r#"
-        SELECT EXISTS(
-            SELECT 1 FROM categories WHERE id = ?
-        ) AS exists_flag
-        "#,
-        cat_id
-    )
-            .fetch_one(&pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to check category existence: {:?}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-
-        if category_exists == 0 {
-            tracing::warn!("Category with ID {} does not exist", cat_id);
-            return Err(StatusCode::BAD_REQUEST);
+    ensure_owner(&claims, &existing)?;
+
+    let mut errors = validate_item_fields(
+        Some(&payload.name),
+        payload.description.as_deref(),
+        Some(&payload.price),
+        Some(payload.quantity),
+    );
+    check_category(&state, payload.category_id, &mut errors).await?;
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid item payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    let changes = UpdateItem {
+        name: Some(payload.name),
+        description: Some(payload.description),
+        price: Some(payload.price),
+        quantity: Some(payload.quantity),
+        category_id: Some(payload.category_id),
+    };
+
+    let updated = state.items.update(id, &changes).await?;
+
+    updated
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Item {} not found.", id)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = i64, Path, description = "Item id")),
+    request_body = UpdateItem,
+    responses(
+        (status = 200, description = "The updated item", body = Item),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn patch_item(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<UpdateItem>,
+) -> Result<Json<Item>, AppError> {
+    tracing::info!("PATCH /items/{}: {:?}", id, payload);
+
+    let existing = state.items.get(id).await?;
+
+    let existing = match existing {
+        Some(item) => item,
+        None => {
+            tracing::warn!("Item {} not found for update", id);
+            return Err(AppError::NotFound(format!("Item {} not found.", id)));
         }
+    };
+
+    ensure_owner(&claims, &existing)?;
+
+    let mut errors = validate_item_fields(
+        payload.name.as_deref(),
+        payload.description.as_ref().and_then(|d| d.as_deref()),
+        payload.price.as_ref(),
+        payload.quantity,
+    );
+    check_category(&state, payload.category_id.flatten(), &mut errors).await?;
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid item payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
     }
 
-    let name = payload.name;
-    let price = payload.price;
-    let quantity = payload.quantity;
-    let category_id = payload.category_id;
-
-    sqlx::query!(
-        r#"
-        UPDATE items
-        SET name = ?, price = ?, quantity = ?, category_id = ?
-        WHERE id = ?
-        "#,
-        name,
-        price,
-        quantity,
-        category_id,
-        id
-    )
-        .execute(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Update failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let updated = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE id = ?
-        "#,
-        id
-    )
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to fetch updated item: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let updated = state.items.update(id, &payload).await?;
 
-    Ok(Json(updated))
+    updated
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Item {} not found.", id)))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = i64, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Item deleted", body = serde_json::Value, example = json!({ "message": "..." })),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn delete_item(
     Path(id): Path<i64>,
-    State(pool): State<MySqlPool>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<serde_json::Value>, AppError> {
     tracing::info!("DELETE /items/{}", id);
 
-    let existing = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE id = ?
-        "#,
-        id
-    )
-        .fetch_optional(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("DB error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let _existing = match existing {
+    let existing = state.items.get(id).await?;
+
+    let existing = match existing {
         Some(item) => item,
         None => {
             tracing::warn!("Item {} not found for update", id);
-            return Err(StatusCode::NOT_FOUND);
+            return Err(AppError::NotFound(format!("Item {} not found.", id)));
         }
     };
 
-    sqlx::query!(
-        r#"
-        DELETE FROM items WHERE id = ?
-        "#,
-        id
-    )
-        .execute(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Delete failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    ensure_owner(&claims, &existing)?;
+
+    state.items.delete(id).await?;
 
     Ok(Json(json!({ "message": "Item has been removed." })))
 }
 
+#[utoipa::path(
+    get,
+    path = "/items/category/{id}",
+    tag = "items",
+    params(("id" = i64, Path, description = "Category id"), CategoryItemsQuery, PageQuery),
+    responses(
+        (status = 200, description = "One page of items of the category", body = Page<Item>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_items_by_category(
     Path(id): Path<i64>,
-    State(pool): State<MySqlPool>,
-) -> Result<Json<Vec<Item>>, StatusCode> {
-    tracing::info!("GET /items/category/{}", id);
-
-    let items = sqlx::query_as!(
-        Item,
-        r#"
-    SELECT id, name, price, quantity, category_id
-    FROM items
-    WHERE category_id = ?
-    "#,
-        id
-    )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Database error: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    Ok(Json(items))
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<CategoryItemsQuery>,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<Item>>), AppError> {
+    tracing::info!("GET /items/category/{}: {:?}", id, params);
+
+    let category_ids = if params.include_descendants {
+        with_descendants(&state, vec![id]).await?
+    } else {
+        vec![id]
+    };
+
+    let filter = ItemFilter {
+        category_ids,
+        ..ItemFilter::default()
+    };
+
+    list_items(&state, &uri, &filter, &paging).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/me/items",
+    tag = "items",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "One page of items of the caller", body = Page<Item>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_my_items(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    OriginalUri(uri): OriginalUri,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<Item>>), AppError> {
+    tracing::info!("GET /me/items for {}", claims.sub);
+
+    let filter = ItemFilter {
+        owner_id: Some(claims.uid),
+        ..ItemFilter::default()
+    };
+
+    list_items(&state, &uri, &filter, &paging).await
+}
+
+/// The given categories and all their subcategories. Unknown ids are kept,
+/// so they still match nothing rather than lifting the filter.
+async fn with_descendants(state: &AppState, ids: Vec<i64>) -> Result<Vec<i64>, AppError> {
+    let found = state.categories.descendant_ids(&ids).await?;
+
+    Ok(if found.is_empty() { ids } else { found })
+}
+
+/// One page of the items matching `filter`, by id, with its `Link` header.
+async fn list_items(
+    state: &AppState,
+    uri: &Uri,
+    filter: &ItemFilter,
+    paging: &PageQuery,
+) -> Result<(HeaderMap, Json<Page<Item>>), AppError> {
+    let page = paging.resolve(&state.config.pagination)?;
+    let page = state.items.browse(filter, ItemOrder::Id, &page).await?;
+
+    Ok((pagination::links(uri, page.next_cursor.as_deref()), Json(page)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/items/create",
+    tag = "items",
+    request_body = CreateItem,
+    responses(
+        (status = 200, description = "The new item", body = Item),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn create_item(
-    State(pool): State<MySqlPool>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(payload): Json<CreateItem>,
-) -> Result<Json<Item>, StatusCode> {
+) -> Result<Json<Item>, AppError> {
     tracing::info!("POST /items/create: {:?}", payload);
 
-    if payload.name.trim().len() < 3 {
-        tracing::warn!("Invalid name: {}", payload.name);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if payload.price < 0.0 {
-        tracing::warn!("Invalid price: {}", payload.price);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if payload.quantity < 1 {
-        tracing::warn!("Invalid quantity: {}", payload.quantity);
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    if let Some(cat_id) = payload.category_id {
-        let category_exists = sqlx::query_scalar!(
-        r#"
-        SELECT EXISTS(
-            SELECT 1 FROM categories WHERE id = ?
-        ) AS exists_flag
-        "#,
-        cat_id
-    )
-            .fetch_one(&pool)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to check category existence: {:?}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-
-        if category_exists == 0 {
-            tracing::warn!("Category with ID {} does not exist", cat_id);
-            return Err(StatusCode::BAD_REQUEST);
-        }
+    let mut errors = validate_item_fields(
+        Some(&payload.name),
+        payload.description.as_deref(),
+        Some(&payload.price),
+        Some(payload.quantity),
+    );
+    check_category(&state, payload.category_id, &mut errors).await?;
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid item payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
     }
 
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO items (name, price, quantity, category_id)
-        VALUES (?, ?, ?, ?)
-        "#,
-        payload.name,
-        payload.price,
-        payload.quantity,
-        payload.category_id
-    )
-        .execute(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Insert failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let inserted_id = result.last_insert_id() as i64;
-
-    let item = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE id = ?
-        "#,
-        inserted_id
-    )
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Fetch inserted item failed: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let item = state
+        .items
+        .create(NewItem {
+            name: payload.name,
+            description: payload.description,
+            price: payload.price,
+            quantity: payload.quantity,
+            category_id: payload.category_id,
+            owner_id: Some(claims.uid),
+        })
+        .await?;
 
     Ok(Json(item))
 }
 
+#[utoipa::path(
+    get,
+    path = "/items/search",
+    tag = "items",
+    params(ItemQuery, PageQuery),
+    responses(
+        (status = 200, description = "One page of items matching in name or description, best first", body = Page<Item>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn search_items(
-    State(pool): State<MySqlPool>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
     Query(params): Query<ItemQuery>,
-) -> Result<Json<Vec<Item>>, StatusCode> {
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<Item>>), AppError> {
+    tracing::info!(
+        "GET /items/search?name={:?}&page={:?}&page_size={:?}",
+        params.name,
+        params.page,
+        paging.page_size
+    );
+
+    let mut errors = Vec::new();
 
     if let Some(name) = &params.name {
         if name.trim().len() < 2 {
-            tracing::warn!(
-            "Search term must be longer than 2 characters. Invalid search for {}",
-            name
-        );
-            return Err(StatusCode::BAD_REQUEST);
+            errors.push(FieldError::new("name", "must be at least 2 characters"));
         }
     }
 
-    tracing::info!("GET /items?name={:?}&page={:?}&page_size={:?}", params.name, params.page, params.page_size);
-
-    let page = params.page.unwrap_or(1);
-    let page_size = params.page_size.unwrap_or(10);
-    let offset = (page - 1) * page_size;
-
-    let name_filter = params.name.unwrap_or_default();
-    let wildcard = format!("%{}%", name_filter);
-
-    let items = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT id, name, price, quantity, category_id
-        FROM items
-        WHERE name LIKE ?
-        LIMIT ?
-        OFFSET ?
-        "#,
-        wildcard,
-        page_size as i64,
-        offset as i64
-    )
-        .fetch_all(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Database error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    Ok(Json(items))
+    if params.page == Some(0) {
+        errors.push(FieldError::new("page", "must be at least 1"));
+    }
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid search: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    let mut page = paging.resolve(&state.config.pagination)?;
+
+    // A page number is only honoured for the first request; the cursor of
+    // the page it returns takes over from there.
+    if page.after.is_none() {
+        let number = params.page.unwrap_or(1) as u64;
+        page.offset = (number - 1) * page.size as u64;
+    }
+
+    let filter = ItemFilter {
+        search: params.name,
+        ..ItemFilter::default()
+    };
+
+    let page = state
+        .items
+        .browse(&filter, ItemOrder::resolve(&filter, None), &page)
+        .await?;
+
+    Ok((pagination::links(&uri, page.next_cursor.as_deref()), Json(page)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/items/search/category/{category_name}",
+    tag = "items",
+    params(("category_name" = String, Path, description = "Category name"), PageQuery),
+    responses(
+        (status = 200, description = "One page of items of the category", body = Page<Item>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_items_by_category_name(
     Path(category_name): Path<String>,
-    State(pool): State<MySqlPool>,
-) -> Result<Json<Vec<Item>>, StatusCode> {
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<Item>>), AppError> {
     tracing::info!("GET /items/search/category/{}", category_name);
 
-    let category = sqlx::query!(
-        r#"
-        SELECT id FROM categories
-        WHERE name = ?
-        "#,
-        category_name
-    )
-        .fetch_optional(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Category check DB error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let category = state.categories.find_by_name(&category_name).await?;
 
     let category = match category {
         Some(cat) => cat,
         None => {
             tracing::warn!("Category '{}' not found", category_name);
-            return Err(StatusCode::NOT_FOUND);
+            return Err(AppError::NotFound(format!("Category '{}' not found.", category_name)));
         }
     };
 
-    let items = sqlx::query_as!(
-        Item,
-        r#"
-        SELECT i.id, i.name, i.price, i.quantity, i.category_id
-        FROM items i
-        WHERE i.category_id = ?
-        "#,
-        category.id
-    )
-        .fetch_all(&pool)
-        .await
-        .map_err(|e| {
-            tracing::error!("Item fetch DB error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    Ok(Json(items))
-}
+    let filter = ItemFilter {
+        category_ids: vec![category.id],
+        ..ItemFilter::default()
+    };
 
+    list_items(&state, &uri, &filter, &paging).await
+}
 
 // CATEGORIES
 
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "One page of categories, by id", body = Page<Category>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_all_categories(
-    State(pool): State<MySqlPool>,
-) -> Result<Json<Vec<Category>>, StatusCode> {
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<Category>>), AppError> {
     tracing::info!("GET /categories");
 
-    let categories = sqlx::query_as!(
-        Category,
-        r#"
-        SELECT id, name FROM categories
-        "#
-    )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to fetch categories: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    Ok(Json(categories))
+    let page = paging.resolve(&state.config.pagination)?;
+    let categories = state.categories.list(&page).await?;
+
+    Ok((pagination::links(&uri, categories.next_cursor.as_deref()), Json(categories)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i64, Path, description = "Category id")),
+    responses(
+        (status = 200, description = "The category", body = Category),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
 pub async fn get_category_by_id(
     Path(id): Path<i64>,
-    State(pool): State<MySqlPool>,
-) -> Result<Json<Category>, StatusCode> {
+    State(state): State<AppState>,
+) -> Result<Json<Category>, AppError> {
     tracing::info!("GET /categories/{}", id);
 
-    let category = sqlx::query_as!(
-        Category,
-        r#"
-        SELECT id, name
-        FROM categories
-        WHERE id = ?
-        "#,
-        id
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("DB error: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let category = state.categories.get(id).await?;
 
     match category {
         Some(c) => Ok(Json(c)),
         None => {
             tracing::error!("Category with id {} is not found", id);
-            Err(StatusCode::NOT_FOUND)
+            Err(AppError::NotFound(format!("Category {} not found.", id)))
+        }
+    }
+}
+
+/// Arranges every category into trees, one per top-level category, with
+/// subcategories sorted by name.
+#[utoipa::path(
+    get,
+    path = "/categories/tree",
+    tag = "categories",
+    responses(
+        (status = 200, description = "Top-level categories with their subcategories", body = Vec<CategoryNode>),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_category_tree(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<CategoryNode>>, AppError> {
+    tracing::info!("GET /categories/tree");
+
+    let categories = state.categories.all().await?;
+
+    Ok(Json(CategoryNode::forest(categories)))
+}
+
+/// Breadcrumb of a category: its ancestors from the top level down, ending
+/// with the category itself.
+#[utoipa::path(
+    get,
+    path = "/categories/{id}/path",
+    tag = "categories",
+    params(("id" = i64, Path, description = "Category id")),
+    responses(
+        (status = 200, description = "The category and its ancestors, top-level first", body = Vec<Category>),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_category_path(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Category>>, AppError> {
+    tracing::info!("GET /categories/{}/path", id);
+
+    let path = state.categories.ancestors(id).await?;
+
+    if path.is_empty() {
+        tracing::warn!("Category {} not found for path", id);
+        return Err(AppError::NotFound(format!("Category {} not found.", id)));
+    }
+
+    Ok(Json(path))
+}
+
+/// Checks that `parent_id` exists. Whether a move would form a cycle is
+/// checked by the store, together with the write.
+async fn check_parent(
+    state: &AppState,
+    parent_id: Option<i64>,
+    errors: &mut Vec<FieldError>,
+) -> Result<(), AppError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+
+    let parent = state.categories.get(parent_id).await?;
+
+    if parent.is_none() {
+        errors.push(FieldError::new(
+            "parent_id",
+            format!("category {} does not exist", parent_id),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Category names are unique among siblings, ignoring case.
+async fn check_sibling_name(
+    state: &AppState,
+    id: Option<i64>,
+    parent_id: Option<i64>,
+    name: &str,
+) -> Result<(), AppError> {
+    let sibling = state.categories.find_child(parent_id, name).await?;
+
+    match sibling {
+        Some(sibling) if Some(sibling.id) != id => {
+            tracing::warn!("Category name {} is taken under {:?}", name, parent_id);
+            Err(AppError::Conflict(
+                "category_name_taken",
+                format!("A category named '{}' already exists at this level.", sibling.name),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/categories",
+    tag = "categories",
+    request_body = CreateCategory,
+    responses(
+        (status = 200, description = "The new category", body = Category),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn create_category(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateCategory>,
+) -> Result<Json<Category>, AppError> {
+    tracing::info!("POST /categories: {:?}", payload);
+
+    let mut errors = Vec::new();
+
+    if payload.name.trim().len() < 3 {
+        errors.push(FieldError::new("name", "must be at least 3 characters"));
+    }
+    check_parent(&state, payload.parent_id, &mut errors).await?;
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid category payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    check_sibling_name(&state, None, payload.parent_id, &payload.name).await?;
+
+    let category = state
+        .categories
+        .create(&payload.name, payload.parent_id)
+        .await?;
+
+    Ok(Json(category))
+}
+
+/// Renames a category and/or moves it, with its subcategories, under
+/// another parent.
+#[utoipa::path(
+    patch,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i64, Path, description = "Category id")),
+    request_body = UpdateCategory,
+    responses(
+        (status = 200, description = "The updated category", body = Category),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn update_category(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateCategory>,
+) -> Result<Json<Category>, AppError> {
+    tracing::info!("PATCH /categories/{}: {:?}", id, payload);
+
+    let existing = state.categories.get(id).await?;
+
+    let existing = match existing {
+        Some(category) => category,
+        None => {
+            tracing::warn!("Category {} not found for update", id);
+            return Err(AppError::NotFound(format!("Category {} not found.", id)));
+        }
+    };
+
+    let mut errors = Vec::new();
+
+    if let Some(name) = &payload.name {
+        if name.trim().len() < 3 {
+            errors.push(FieldError::new("name", "must be at least 3 characters"));
         }
     }
+    if let Some(parent_id) = payload.parent_id {
+        check_parent(&state, parent_id, &mut errors).await?;
+    }
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid category payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    let updated = Category {
+        id,
+        parent_id: payload.parent_id.unwrap_or(existing.parent_id),
+        name: payload.name.unwrap_or(existing.name),
+    };
+
+    check_sibling_name(&state, Some(id), updated.parent_id, &updated.name).await?;
+
+    state.categories.update(&updated).await?;
+
+    Ok(Json(updated))
+}
+
+/// Deleting a category that still has items or subcategories is rejected
+/// with `409 Conflict`; they have to be moved or deleted first.
+#[utoipa::path(
+    delete,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i64, Path, description = "Category id")),
+    responses(
+        (status = 200, description = "Category deleted", body = serde_json::Value, example = json!({ "message": "..." })),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn delete_category(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    tracing::info!("DELETE /categories/{}", id);
+
+    let existing = state.categories.get(id).await?;
+
+    if existing.is_none() {
+        tracing::warn!("Category {} not found for delete", id);
+        return Err(AppError::NotFound(format!("Category {} not found.", id)));
+    }
+
+    let has_items = state.categories.has_items(id).await?;
+
+    if has_items {
+        tracing::warn!("Category {} still has items, refusing to delete", id);
+        return Err(AppError::Conflict(
+            "category_in_use",
+            format!("Category {} still has items; move or delete them first.", id),
+        ));
+    }
+
+    let has_children = state.categories.has_children(id).await?;
+
+    if has_children {
+        tracing::warn!("Category {} still has subcategories, refusing to delete", id);
+        return Err(AppError::Conflict(
+            "category_has_children",
+            format!("Category {} still has subcategories; move or delete them first.", id),
+        ));
+    }
+
+    state.categories.delete(id).await?;
+
+    Ok(Json(json!({ "message": "Category has been removed." })))
 }
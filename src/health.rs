@@ -0,0 +1,107 @@
+//! Probes for load balancers and orchestrators. None of them need a token.
+
+use crate::state::AppState;
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Longest the readiness probe waits for the database.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "ops",
+    responses((status = 200, description = "The process is alive", body = Value, example = json!({ "status": "ok" }))),
+)]
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the database answers and its schema is current. Returns 503
+/// otherwise, so no traffic is routed here. Pool usage is reported but does
+/// not affect the result; a busy pool still serves requests, just slower.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "ops",
+    responses(
+        (status = 200, description = "Database reachable and schema current", body = Value),
+        (status = 503, description = "Not ready; `checks` tells why", body = Value),
+    ),
+)]
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    let database = match tokio::time::timeout(PING_TIMEOUT, state.health.ping()).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) => false,
+        Err(_) => {
+            tracing::warn!("Readiness ping timed out after {:?}", PING_TIMEOUT);
+            false
+        }
+    };
+
+    // Without a database the migrations cannot be checked either.
+    let pending = if database {
+        state.health.pending_migrations().await.ok().flatten()
+    } else {
+        None
+    };
+
+    let ready = database && pending == Some(0);
+
+    let mut checks = json!({
+        "database": { "status": if database { "ok" } else { "unreachable" } },
+        "migrations": match pending {
+            Some(0) => json!({ "status": "ok", "pending": 0 }),
+            Some(n) => json!({ "status": "pending", "pending": n }),
+            None => json!({ "status": "unknown" }),
+        },
+    });
+
+    if let Some(pool) = state.health.pool_stats() {
+        let in_use = pool.size.saturating_sub(pool.idle);
+
+        checks["pool"] = json!({
+            "size": pool.size,
+            "idle": pool.idle,
+            "in_use": in_use,
+            "max": pool.max,
+            "saturation": in_use as f64 / pool.max.max(1) as f64,
+        });
+    }
+
+    if !ready {
+        tracing::warn!("Not ready: {}", checks);
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": checks,
+    });
+
+    (status, Json(body))
+}
+
+/// Version of the crate and how the binary was built.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "ops",
+    responses((status = 200, description = "Build information", body = Value)),
+)]
+pub async fn version() -> Json<Value> {
+    Json(json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": env!("BUILD_GIT_COMMIT"),
+        "profile": env!("BUILD_PROFILE"),
+        "rustc": env!("BUILD_RUSTC_VERSION"),
+    }))
+}
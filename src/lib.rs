@@ -0,0 +1,21 @@
+pub mod auth;
+pub mod auth_middleware;
+pub mod cart;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod extract;
+pub mod handlers;
+pub mod health;
+pub mod metrics;
+pub mod models;
+pub mod money;
+pub mod openapi;
+pub mod orders;
+pub mod pagination;
+pub mod repo;
+pub mod request_id;
+pub mod routes;
+pub mod search;
+pub mod state;
+pub mod telemetry;
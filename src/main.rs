@@ -1,28 +1,156 @@
 use dotenvy::dotenv;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
+use store::{
+    config::{Command, Config},
+    db, routes,
+    state::AppState,
+    telemetry,
+};
 use tokio::net::TcpListener;
 
-mod db;
-mod handlers;
-mod models;
-mod routes;
-mod auth;
-mod auth_middleware;
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    tracing_subscriber::fmt::init();
 
-    let db = db::init_db_pool().await;
-    let app = routes::create_routes(db.clone());
+    // `store migrate` applies migrations and exits without serving.
+    let command = Command::from_args();
+
+    let config = match Config::load(command) {
+        Ok(c) => c,
+        Err(e) => fail(e),
+    };
+
+    // Flushes pending spans when dropped at the end of `main`.
+    let _telemetry = match telemetry::init(&config.log, &config.otel) {
+        Ok(t) => t,
+        Err(e) => fail(e),
+    };
+
+    let db = match db::init_db_pool(&config.database).await {
+        Ok(db) => db,
+        Err(e) => fail(e),
+    };
+
+    let migrate_only = command == Command::Migrate;
+
+    if migrate_only || config.database.auto_migrate {
+        if let Err(e) = db::run_migrations(&db).await {
+            fail(e);
+        }
+    } else {
+        match db::check_schema(&db).await {
+            Ok(0) => {}
+            Ok(pending) => fail(format!("{} migration(s) pending, run `store migrate` first.", pending)),
+            Err(e) => fail(e),
+        }
+    }
+
+    if migrate_only {
+        println!("✅ Database schema is up to date");
+        db.close().await;
+        return;
+    }
+
+    let addr = SocketAddr::new(config.server.host, config.server.port);
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+
+    let app = routes::create_routes(AppState::mysql(db.clone(), config));
+
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => fail(format!("cannot listen on {}: {}", addr, e)),
+    };
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("🚀 Server running at http://{}", addr);
 
-    let listener = TcpListener::bind(addr).await.unwrap();
+    // The server stops accepting connections once `stop` fires and returns
+    // when every in-flight request has finished.
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let mut server = tokio::spawn(async move {
+        axum::serve::serve(listener, app)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            })
+            .await
+    });
+
+    tokio::select! {
+        result = &mut server => {
+            db.close().await;
+            match result {
+                Ok(Ok(())) => fail("server stopped unexpectedly"),
+                Ok(Err(e)) => fail(format!("server failed: {}", e)),
+                Err(e) => fail(format!("server task failed: {}", e)),
+            }
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    tracing::info!(
+        "Shutdown requested, draining in-flight requests for up to {:?}",
+        drain_timeout
+    );
+    stop.send(()).ok();
+
+    let deadline = tokio::time::Instant::now() + drain_timeout;
+
+    match tokio::time::timeout_at(deadline, &mut server).await {
+        Ok(_) => tracing::info!("All requests finished"),
+        Err(_) => {
+            // Aborting `server` would only stop its accept loop, not the
+            // connection tasks it spawned. Those are dropped with the
+            // runtime when `main` returns; their database connections close
+            // and MySQL rolls back whatever transactions they left open.
+            tracing::warn!(
+                "Drain timed out after {:?}, exiting with requests still running",
+                drain_timeout
+            );
+        }
+    }
+
+    // `close` waits for every checked-out connection, including those of
+    // requests that are still running, so it gets only what is left of the
+    // drain timeout.
+    if tokio::time::timeout_at(deadline, db.close()).await.is_err() {
+        tracing::warn!("Database pool did not close in time");
+    }
+
+    println!("👋 Server stopped");
+}
+
+/// Resolves on Ctrl+C (SIGINT) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Listening for Ctrl+C failed: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Listening for SIGTERM failed: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
 
-    axum::serve::serve(listener, app)
-        .await
-        .unwrap();
+/// Reports a startup error and exits.
+fn fail(error: impl std::fmt::Display) -> ! {
+    eprintln!("❌ {}", error);
+    std::process::exit(1);
 }
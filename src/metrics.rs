@@ -0,0 +1,229 @@
+//! Prometheus metrics, served as text on `/metrics`.
+//!
+//! Request metrics are labelled with the route template (`/items/:id`), so
+//! the number of series does not grow with the ids in the paths. Pool and
+//! stock gauges are refreshed on every scrape.
+
+use crate::state::AppState;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::time::{Duration, Instant};
+
+/// Longest a scrape waits for a pooled connection.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Every metric the application exports, in a registry of its own.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    logins: IntCounterVec,
+    pool_connections: IntGaugeVec,
+    pool_max: IntGauge,
+    pool_probe: Gauge,
+    low_stock_items: IntGauge,
+    item_stock: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route template and status class."),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency.")
+                .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+            &["method", "route"],
+        )
+        .expect("valid metric");
+
+        let logins = IntCounterVec::new(
+            Opts::new("auth_logins_total", "Login attempts by result."),
+            &["result"],
+        )
+        .expect("valid metric");
+
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Open pool connections by state."),
+            &["state"],
+        )
+        .expect("valid metric");
+
+        let pool_max = IntGauge::new("db_pool_max_connections", "Configured pool size.")
+            .expect("valid metric");
+
+        let pool_probe = Gauge::new(
+            "db_pool_probe_seconds",
+            "Time the last scrape took to get a pooled connection and ping MySQL; \
+             high values mean requests are waiting for connections.",
+        )
+        .expect("valid metric");
+
+        let low_stock_items = IntGauge::new(
+            "store_low_stock_items",
+            "Items at or below the low-stock threshold, up to the reporting limit.",
+        )
+        .expect("valid metric");
+
+        let item_stock = IntGaugeVec::new(
+            Opts::new("store_item_stock", "Stock of each low-stock item."),
+            &["item_id", "name"],
+        )
+        .expect("valid metric");
+
+        for collector in [
+            Box::new(http_requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http_duration.clone()),
+            Box::new(logins.clone()),
+            Box::new(pool_connections.clone()),
+            Box::new(pool_max.clone()),
+            Box::new(pool_probe.clone()),
+            Box::new(low_stock_items.clone()),
+            Box::new(item_stock.clone()),
+        ] {
+            registry.register(collector).expect("unique metric names");
+        }
+
+        // Both results show up from the first scrape, at zero.
+        for result in ["success", "failure"] {
+            logins.with_label_values(&[result]);
+        }
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            logins,
+            pool_connections,
+            pool_max,
+            pool_probe,
+            low_stock_items,
+            item_stock,
+        }
+    }
+
+    pub fn login(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.logins.with_label_values(&[result]).inc();
+    }
+
+    fn observe_request(&self, method: &str, route: &str, status: StatusCode, elapsed: Duration) {
+        let class = match status.as_u16() {
+            100..=199 => "1xx",
+            200..=299 => "2xx",
+            300..=399 => "3xx",
+            400..=499 => "4xx",
+            _ => "5xx",
+        };
+
+        self.http_requests
+            .with_label_values(&[method, route, class])
+            .inc();
+        self.http_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts every request and records its latency.
+pub async fn track_metrics(State(state): State<AppState>, req: Request<Body>, next: Next) -> Response {
+    // Unmatched paths are grouped so that scanners cannot create series.
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+
+    let method = match *req.method() {
+        Method::GET | Method::POST | Method::PUT | Method::PATCH | Method::DELETE | Method::HEAD
+        | Method::OPTIONS => req.method().as_str().to_owned(),
+        _ => "other".into(),
+    };
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    state
+        .metrics
+        .observe_request(&method, &route, response.status(), start.elapsed());
+
+    response
+}
+
+/// Refreshes the pool and stock gauges and renders every metric.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "ops",
+    responses((status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain")),
+)]
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let metrics = &state.metrics;
+
+    if let Some(pool) = state.health.pool_stats() {
+        let in_use = pool.size.saturating_sub(pool.idle);
+
+        metrics.pool_connections.with_label_values(&["idle"]).set(pool.idle as i64);
+        metrics.pool_connections.with_label_values(&["in_use"]).set(in_use as i64);
+        metrics.pool_max.set(pool.max as i64);
+
+        let start = Instant::now();
+        match tokio::time::timeout(PROBE_TIMEOUT, state.health.ping()).await {
+            Ok(Ok(())) => metrics.pool_probe.set(start.elapsed().as_secs_f64()),
+            _ => metrics.pool_probe.set(PROBE_TIMEOUT.as_secs_f64()),
+        }
+    }
+
+    let low_stock = &state.config.metrics;
+    match state
+        .items
+        .low_stock(low_stock.low_stock_threshold, low_stock.low_stock_limit)
+        .await
+    {
+        Ok(items) => {
+            // Items that were restocked or deleted drop out of the series.
+            metrics.item_stock.reset();
+            metrics.low_stock_items.set(items.len() as i64);
+
+            for item in items {
+                metrics
+                    .item_stock
+                    .with_label_values(&[&item.id.to_string(), &item.name])
+                    .set(item.quantity as i64);
+            }
+        }
+        Err(e) => tracing::warn!("Low-stock gauges not refreshed: {:?}", e),
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&metrics.registry.gather(), &mut buffer) {
+        tracing::error!("Encoding metrics failed: {:?}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        buffer,
+    )
+        .into_response()
+}
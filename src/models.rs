@@ -1,84 +1,537 @@
-use serde::{Deserialize, Serialize};
+use crate::money::{Currency, Money};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Category {
     pub id: i64,
+    /// Enclosing category; `None` for top-level categories.
+    pub parent_id: Option<i64>,
     pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateCategory {
     pub name: String,
+    /// Category to nest the new one under; omitted for a top-level one.
+    #[serde(default)]
+    pub parent_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateCategory {
     pub name: Option<String>,
+    /// Absent keeps the parent, `null` moves the category to the top level.
+    #[serde(default, deserialize_with = "deserialize_present", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Option<i64>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+/// A category with its subcategories, as returned by `/categories/tree`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryNode {
+    pub id: i64,
+    pub name: String,
+    /// Subcategories, by name.
+    #[schema(no_recursion)]
+    pub children: Vec<CategoryNode>,
+}
+
+impl CategoryNode {
+    /// Arranges a flat list of categories into trees, one per top-level
+    /// category.
+    pub fn forest(categories: Vec<Category>) -> Vec<CategoryNode> {
+        let mut by_parent: HashMap<Option<i64>, Vec<Category>> = HashMap::new();
+        for category in categories {
+            by_parent.entry(category.parent_id).or_default().push(category);
+        }
+
+        fn children(
+            parent: Option<i64>,
+            by_parent: &mut HashMap<Option<i64>, Vec<Category>>,
+        ) -> Vec<CategoryNode> {
+            let mut categories = by_parent.remove(&parent).unwrap_or_default();
+            categories.sort_by_key(|c| (c.name.to_lowercase(), c.id));
+
+            categories
+                .into_iter()
+                .map(|c| CategoryNode {
+                    id: c.id,
+                    name: c.name,
+                    children: children(Some(c.id), by_parent),
+                })
+                .collect()
+        }
+
+        children(None, &mut by_parent)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CategoryItemsQuery {
+    /// Also list the items of every subcategory, at any depth.
+    #[serde(default)]
+    pub include_descendants: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Item {
     pub id: i64,
     pub name: String,
-    pub price: f64,
+    pub description: Option<String>,
+    pub price: Money,
     pub quantity: i32,
     pub category_id: Option<i64>,
+    /// User who created the item; `None` for items created before ownership was tracked.
+    pub owner_id: Option<i64>,
+    pub created_at: NaiveDateTime,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Row shape of `items`, where the price is split over `price` and `currency`.
+#[derive(Debug, FromRow)]
+pub struct ItemRow {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub price: Decimal,
+    pub currency: Currency,
+    pub quantity: i32,
+    pub category_id: Option<i64>,
+    pub owner_id: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<ItemRow> for Item {
+    fn from(row: ItemRow) -> Self {
+        Item {
+            id: row.id,
+            name: row.name,
+            description: row.description,
+            price: Money::new(row.price, row.currency),
+            quantity: row.quantity,
+            category_id: row.category_id,
+            owner_id: row.owner_id,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateItem {
     pub name: String,
-    pub price: f64,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub price: Money,
     pub quantity: i32,
     pub category_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateItem {
     pub name: Option<String>,
-    pub price: Option<f64>,
+    /// Absent keeps the description, `null` clears it.
+    #[serde(default, deserialize_with = "deserialize_present", skip_serializing_if = "Option::is_none")]
+    pub description: Option<Option<String>>,
+    pub price: Option<Money>,
     pub quantity: Option<i32>,
-    pub category_id: Option<i64>,
+    /// `None` when the field is absent (keep the current category),
+    /// `Some(None)` when it is explicitly `null` (clear the category).
+    #[serde(default, deserialize_with = "deserialize_present", skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<Option<i64>>,
+}
+
+/// Wraps any value that is present in the payload, including `null`, in `Some`.
+/// Combined with `#[serde(default)]` this tells an absent field apart from a `null` one.
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct User {
     pub id: i64,
     pub username: String,
     pub password_hash: String,
     pub role_id: i64,
+    pub token_version: i64,
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct Role {
     pub id: i64,
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterUser {
     pub username: String,
     pub password: String,
     pub role: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    /// Access token lifetime in seconds.
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct LogoutRequest {
+    /// Refresh token to revoke together with the access token.
+    pub refresh_token: Option<String>,
+    /// Revoke every token of the user, on all devices.
+    #[serde(default)]
+    pub everywhere: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangeRoleRequest {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserResponse {
     pub id: i64,
     pub username: String,
     pub role_id: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddCartItem {
+    pub item_id: i64,
+    pub quantity: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateCartItem {
+    pub quantity: i32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CartLine {
+    pub item_id: i64,
+    pub name: String,
+    /// Current price of the item.
+    pub unit_price: Money,
+    pub quantity: i32,
+    pub line_total: Money,
+    /// Units currently in stock.
+    pub available: i32,
+    /// Set when the price changed since the cart was last shown.
+    pub price_changed: bool,
+    pub previous_price: Option<Money>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CartResponse {
+    pub lines: Vec<CartLine>,
+    /// `None` for an empty cart.
+    pub total: Option<Money>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CheckoutRequest {
+    /// Lines to buy; when empty the caller's cart is checked out.
+    #[serde(default)]
+    pub lines: Vec<CheckoutLine>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CheckoutLine {
+    pub item_id: i64,
+    pub quantity: i32,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Order {
+    pub id: i64,
+    pub user_id: i64,
+    /// One of the [`OrderStatus`] names.
+    #[schema(value_type = OrderStatus)]
+    pub status: String,
+    pub total: Money,
+    pub created_at: NaiveDateTime,
+    pub paid_at: Option<NaiveDateTime>,
+    pub shipped_at: Option<NaiveDateTime>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub cancelled_at: Option<NaiveDateTime>,
+    pub refunded_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct OrderRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub status: String,
+    pub total: Decimal,
+    pub currency: Currency,
+    pub created_at: NaiveDateTime,
+    pub paid_at: Option<NaiveDateTime>,
+    pub shipped_at: Option<NaiveDateTime>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub cancelled_at: Option<NaiveDateTime>,
+    pub refunded_at: Option<NaiveDateTime>,
+}
+
+impl From<OrderRow> for Order {
+    fn from(row: OrderRow) -> Self {
+        Order {
+            id: row.id,
+            user_id: row.user_id,
+            status: row.status,
+            total: Money::new(row.total, row.currency),
+            created_at: row.created_at,
+            paid_at: row.paid_at,
+            shipped_at: row.shipped_at,
+            delivered_at: row.delivered_at,
+            cancelled_at: row.cancelled_at,
+            refunded_at: row.refunded_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(OrderStatus::Pending),
+            "paid" => Some(OrderStatus::Paid),
+            "shipped" => Some(OrderStatus::Shipped),
+            "delivered" => Some(OrderStatus::Delivered),
+            "cancelled" => Some(OrderStatus::Cancelled),
+            "refunded" => Some(OrderStatus::Refunded),
+            _ => None,
+        }
+    }
+
+    /// States reachable from this one. Orders can be cancelled until they
+    /// ship; refunds are only for delivered orders.
+    pub fn next_states(self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Pending => &[OrderStatus::Paid, OrderStatus::Cancelled],
+            OrderStatus::Paid => &[OrderStatus::Shipped, OrderStatus::Cancelled],
+            OrderStatus::Shipped => &[OrderStatus::Delivered],
+            OrderStatus::Delivered => &[OrderStatus::Refunded],
+            OrderStatus::Cancelled | OrderStatus::Refunded => &[],
+        }
+    }
+
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        self.next_states().contains(&next)
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateOrderStatus {
+    pub status: OrderStatus,
+}
+
+/// Snapshot of an item at checkout time; `item_id` becomes `None` if the
+/// item is deleted later.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OrderLine {
+    pub item_id: Option<i64>,
+    pub seller_id: Option<i64>,
+    pub name: String,
+    pub unit_price: Money,
+    pub quantity: i32,
+}
+
+#[derive(Debug, FromRow)]
+pub struct OrderLineRow {
+    pub item_id: Option<i64>,
+    pub seller_id: Option<i64>,
+    pub name: String,
+    pub unit_price: Decimal,
+    pub currency: Currency,
+    pub quantity: i32,
+}
+
+impl From<OrderLineRow> for OrderLine {
+    fn from(row: OrderLineRow) -> Self {
+        OrderLine {
+            item_id: row.item_id,
+            seller_id: row.seller_id,
+            name: row.name,
+            unit_price: Money::new(row.unit_price, row.currency),
+            quantity: row.quantity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OrderDetail {
+    #[serde(flatten)]
+    pub order: Order,
+    pub lines: Vec<OrderLine>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ItemQuery {
+    /// Words to look for in the name and description; a word may be cut
+    /// short (`keyb`). Without it every item is listed.
     pub name: Option<String>,
+    /// Page number, from 1; ignored when a `cursor` is given.
     pub page: Option<u32>,
-    pub page_size: Option<u32>,
+}
+
+/// Sort keys of the item listing. Only these names parse, so nothing from
+/// the query string reaches an `ORDER BY` as text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemSort {
+    Price,
+    Name,
+    Newest,
+    /// Units sold in orders that were not cancelled or refunded.
+    Popularity,
+}
+
+impl ItemSort {
+    /// Direction used when `order` is not given: cheapest, A to Z, newest
+    /// and best-selling first.
+    pub fn default_order(self) -> SortOrder {
+        match self {
+            ItemSort::Price | ItemSort::Name => SortOrder::Asc,
+            ItemSort::Newest | ItemSort::Popularity => SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemListQuery {
+    /// Words to look for in the name and description, as in `/items/search`.
+    pub q: Option<String>,
+    #[param(value_type = Option<String>, example = "10.00")]
+    pub min_price: Option<Decimal>,
+    #[param(value_type = Option<String>, example = "50.00")]
+    pub max_price: Option<Decimal>,
+    /// Only items priced in this currency.
+    pub currency: Option<Currency>,
+    /// Only items with stock left.
+    #[serde(default)]
+    pub in_stock: bool,
+    /// Comma-separated category ids, any of which may match.
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    #[param(value_type = Option<String>, example = "1,4")]
+    pub category: Vec<i64>,
+    /// Let `category` match items of subcategories too, at any depth.
+    #[serde(default)]
+    pub include_descendants: bool,
+    /// Without it, items are ordered by relevance when `q` is given and by
+    /// id otherwise.
+    pub sort: Option<ItemSort>,
+    pub order: Option<SortOrder>,
+}
+
+/// Parses `1,4` into `[1, 4]`.
+fn deserialize_ids<'de, D>(deserializer: D) -> Result<Vec<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+
+    raw.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|_| de::Error::custom(format!("invalid category id `{}`", id))))
+        .collect()
+}
+
+/// Items of a category that pass the other filters.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryFacet {
+    /// `None` counts the items without a category.
+    pub category_id: Option<i64>,
+    pub name: Option<String>,
+    pub count: i64,
+}
+
+/// Items in a price range that pass the other filters.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PriceFacet {
+    /// Inclusive.
+    #[schema(value_type = String, example = "10")]
+    pub min: Decimal,
+    /// Exclusive; `None` for the last bucket.
+    #[schema(value_type = Option<String>, example = "25")]
+    pub max: Option<Decimal>,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemFacets {
+    /// Largest first.
+    pub categories: Vec<CategoryFacet>,
+    /// Every bucket, cheapest first, including empty ones. Empty unless the
+    /// listing is filtered by `currency`, since amounts in different
+    /// currencies do not compare.
+    pub price: Vec<PriceFacet>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemListing {
+    pub items: Vec<Item>,
+    /// Matching items across all pages.
+    pub total: i64,
+    /// Pass as `cursor` to get the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+    pub facets: ItemFacets,
 }
@@ -0,0 +1,183 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::mysql::{MySql, MySqlTypeInfo, MySqlValueRef};
+use sqlx::{Decode, Encode, Type};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// ISO 4217 currencies the store accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Currency {
+    EUR,
+    USD,
+    GBP,
+    CHF,
+    RSD,
+    JPY,
+    KWD,
+}
+
+impl Currency {
+    pub fn code(self) -> &'static str {
+        match self {
+            Currency::EUR => "EUR",
+            Currency::USD => "USD",
+            Currency::GBP => "GBP",
+            Currency::CHF => "CHF",
+            Currency::RSD => "RSD",
+            Currency::JPY => "JPY",
+            Currency::KWD => "KWD",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        match code {
+            "EUR" => Some(Currency::EUR),
+            "USD" => Some(Currency::USD),
+            "GBP" => Some(Currency::GBP),
+            "CHF" => Some(Currency::CHF),
+            "RSD" => Some(Currency::RSD),
+            "JPY" => Some(Currency::JPY),
+            "KWD" => Some(Currency::KWD),
+            _ => None,
+        }
+    }
+
+    /// Number of fraction digits (ISO 4217 minor units).
+    pub fn minor_units(self) -> u32 {
+        match self {
+            Currency::JPY => 0,
+            Currency::KWD => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+// Stored as `CHAR(3)`.
+impl Type<MySql> for Currency {
+    fn type_info() -> MySqlTypeInfo {
+        <str as Type<MySql>>::type_info()
+    }
+
+    fn compatible(ty: &MySqlTypeInfo) -> bool {
+        <str as Type<MySql>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, MySql> for Currency {
+    fn decode(value: MySqlValueRef<'r>) -> Result<Self, BoxDynError> {
+        let code = <&str as Decode<MySql>>::decode(value)?;
+        Currency::parse(code).ok_or_else(|| format!("unknown currency '{}'", code).into())
+    }
+}
+
+impl Encode<'_, MySql> for Currency {
+    fn encode_by_ref(&self, buf: &mut Vec<u8>) -> IsNull {
+        <&str as Encode<MySql>>::encode(self.code(), buf)
+    }
+}
+
+/// Largest amount a `DECIMAL(19,4)` column holds: 999999999999999.9999.
+pub const MAX_AMOUNT: Decimal = Decimal::from_parts(0x89E7_FFFF, 0x8AC7_2304, 0, false, 4);
+
+/// An exact amount in a currency. The amount is (de)serialized as a
+/// decimal string, e.g. `{ "amount": "12.50", "currency": "EUR" }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+pub struct Money {
+    #[serde(with = "rust_decimal::serde::str")]
+    #[schema(value_type = String, example = "12.50")]
+    pub amount: Decimal,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(Decimal::ZERO, currency)
+    }
+
+    /// Checks the amount is not negative, fits the database column and has
+    /// no more fraction digits than the currency allows. The error is meant
+    /// for a field message.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.amount < Decimal::ZERO {
+            return Err("must not be negative".into());
+        }
+
+        if self.amount > MAX_AMOUNT {
+            return Err(format!("must be at most {}", MAX_AMOUNT));
+        }
+
+        let digits = self.amount.normalize().scale();
+        let allowed = self.currency.minor_units();
+
+        if digits > allowed {
+            return Err(format!(
+                "{} allows at most {} fraction digit(s), got {}",
+                self.currency, allowed, digits
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// `None` when the product overflows.
+    pub fn times(&self, quantity: i32) -> Option<Money> {
+        let amount = self.amount.checked_mul(Decimal::from(quantity))?;
+        Some(Money::new(amount, self.currency))
+    }
+
+    /// `None` when the currencies differ.
+    pub fn checked_add(&self, other: &Money) -> Option<Money> {
+        if self.currency != other.currency {
+            return None;
+        }
+
+        Some(Money::new(self.amount + other.amount, self.currency))
+    }
+
+    /// Sums amounts that must all share one currency. `None` for an empty
+    /// iterator or mixed currencies.
+    pub fn sum<'a>(mut values: impl Iterator<Item = &'a Money>) -> Option<Money> {
+        let first = *values.next()?;
+        values.try_fold(first, |acc, m| acc.checked_add(m))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.currency.minor_units() as usize;
+        write!(f, "{:.*} {}", digits, self.amount, self.currency)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Repr {
+            amount: String,
+            currency: Currency,
+        }
+
+        // Always render exactly the currency's minor units, whatever scale
+        // the column returned.
+        let digits = self.currency.minor_units() as usize;
+
+        Repr {
+            amount: format!("{:.*}", digits, self.amount),
+            currency: self.currency,
+        }
+        .serialize(serializer)
+    }
+}
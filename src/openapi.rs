@@ -0,0 +1,103 @@
+//! OpenAPI 3 description of the API, generated from the handler annotations
+//! and the models, served on `/openapi.json`.
+
+use crate::{auth, cart, handlers, health, metrics, orders};
+use axum::Json;
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        OpenApi as OpenApiDoc,
+    },
+    Modify, OpenApi,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Store API",
+        description = "Items, categories, carts and orders of a small web shop. \
+                       Errors are `application/problem+json` documents (RFC 7807)."
+    ),
+    paths(
+        auth::register_user,
+        auth::login_user,
+        auth::refresh_token,
+        auth::logout_user,
+        auth::change_password,
+        auth::change_user_role,
+        handlers::get_all_items,
+        handlers::get_item,
+        handlers::create_item,
+        handlers::update_item,
+        handlers::patch_item,
+        handlers::delete_item,
+        handlers::get_my_items,
+        handlers::get_items_by_category,
+        handlers::search_items,
+        handlers::get_items_by_category_name,
+        handlers::get_all_categories,
+        handlers::get_category_tree,
+        handlers::get_category_by_id,
+        handlers::get_category_path,
+        handlers::create_category,
+        handlers::update_category,
+        handlers::delete_category,
+        cart::get_cart,
+        cart::add_cart_item,
+        cart::update_cart_item,
+        cart::remove_cart_item,
+        cart::clear_cart,
+        orders::checkout,
+        orders::get_my_orders,
+        orders::get_order,
+        orders::get_seller_orders,
+        orders::update_order_status,
+        orders::cancel_order,
+        health::healthz,
+        health::readyz,
+        health::version,
+        metrics::metrics,
+        openapi_json,
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "auth", description = "Registration, tokens and passwords"),
+        (name = "items", description = "Items for sale; sellers manage their own"),
+        (name = "categories", description = "Item categories, nested into a tree"),
+        (name = "cart", description = "Shopping cart of a customer"),
+        (name = "orders", description = "Checkout and order lifecycle"),
+        (name = "admin", description = "User administration"),
+        (name = "ops", description = "Probes, metrics and this document; no token needed"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the `bearer` scheme referenced by the secured operations.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut OpenApiDoc) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Access token from `/auth/login` or `/auth/refresh`."))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// The OpenAPI document of this API.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "ops",
+    responses((status = 200, description = "OpenAPI 3 document", content_type = "application/json")),
+)]
+pub async fn openapi_json() -> Json<OpenApiDoc> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,456 @@
+use crate::auth_middleware::{Claims, ADMIN_ROLE};
+use crate::error::{AppError, FieldError, Problem};
+use crate::models::{CheckoutRequest, Item, OrderDetail, OrderLine, OrderStatus, UpdateOrderStatus};
+use crate::money::{Money, MAX_AMOUNT};
+use crate::pagination::{self, Page, PageQuery};
+use crate::repo::OrderAccess;
+use crate::state::AppState;
+use crate::extract::{Json, OptionalJson, Path, Query};
+use axum::{
+    extract::{OriginalUri, State},
+    http::HeaderMap,
+    Extension,
+};
+use std::collections::BTreeMap;
+
+/// Turns the given lines, or the caller's cart when no lines are sent, into
+/// an order. Stock is checked and decremented under row locks inside one
+/// transaction, so concurrent checkouts cannot oversell.
+#[utoipa::path(
+    post,
+    path = "/orders/checkout",
+    tag = "orders",
+    request_body = Option<CheckoutRequest>,
+    responses(
+        (status = 200, description = "The placed order", body = OrderDetail),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn checkout(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    OptionalJson(payload): OptionalJson<CheckoutRequest>,
+) -> Result<Json<OrderDetail>, AppError> {
+    let payload = payload.unwrap_or_default();
+    let from_cart = payload.lines.is_empty();
+
+    tracing::info!(
+        "POST /orders/checkout for {} ({})",
+        claims.sub,
+        if from_cart { "cart" } else { "lines" }
+    );
+
+    let mut errors = Vec::new();
+    for (i, line) in payload.lines.iter().enumerate() {
+        if line.quantity < 1 {
+            errors.push(FieldError::new(
+                "lines",
+                format!("line {}: quantity must be at least 1", i),
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        tracing::warn!("Invalid checkout payload: {:?}", errors);
+        return Err(AppError::Validation(errors));
+    }
+
+    // Merge duplicate items. Keyed by id, so the store locks rows in id
+    // order and two checkouts touching the same items cannot deadlock.
+    let lines = if from_cart {
+        None
+    } else {
+        let mut quantities: BTreeMap<i64, i32> = BTreeMap::new();
+        for line in &payload.lines {
+            let quantity = quantities.entry(line.item_id).or_insert(0);
+            *quantity = match quantity.checked_add(line.quantity) {
+                Some(sum) => sum,
+                None => {
+                    tracing::warn!("Checkout quantity of item {} overflows", line.item_id);
+                    return Err(AppError::Validation(vec![FieldError::new(
+                        "lines",
+                        format!("item {}: total quantity is too large", line.item_id),
+                    )]));
+                }
+            };
+        }
+        Some(quantities)
+    };
+
+    let detail = state
+        .orders
+        .checkout(claims.uid, lines, &|locked| plan_order(&claims, locked))
+        .await?;
+
+    tracing::info!("Order {} placed by {}", detail.order.id, claims.sub);
+
+    Ok(Json(detail))
+}
+
+/// Prices the locked items of a checkout. Rejects an empty cart, missing
+/// items, quantities above the stock and items priced in different
+/// currencies.
+fn plan_order(
+    claims: &Claims,
+    locked: &[(i64, i32, Option<Item>)],
+) -> Result<(Vec<OrderLine>, Money), AppError> {
+    if locked.is_empty() {
+        tracing::warn!("Checkout with empty cart by {}", claims.sub);
+        return Err(AppError::Conflict("empty_cart", "The cart is empty.".into()));
+    }
+
+    let mut lines = Vec::with_capacity(locked.len());
+
+    for (item_id, quantity, item) in locked {
+        let (item_id, quantity) = (*item_id, *quantity);
+
+        // Lines from the payload are checked above, but cart lines come
+        // straight from the store.
+        if quantity < 1 {
+            tracing::warn!("Checkout of {} x item {}", quantity, item_id);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "lines",
+                format!("item {}: quantity must be at least 1", item_id),
+            )]));
+        }
+
+        let item = match item {
+            Some(i) => i,
+            None => {
+                tracing::warn!("Checkout of missing item {}", item_id);
+                return Err(AppError::Validation(vec![FieldError::new(
+                    "lines",
+                    format!("item {} does not exist", item_id),
+                )]));
+            }
+        };
+
+        if item.quantity < quantity {
+            tracing::warn!(
+                "Checkout of {} x item {}, only {} in stock",
+                quantity,
+                item_id,
+                item.quantity
+            );
+            return Err(AppError::Conflict(
+                "insufficient_stock",
+                format!(
+                    "Only {} of item {} available, {} requested.",
+                    item.quantity, item_id, quantity
+                ),
+            ));
+        }
+
+        lines.push(OrderLine {
+            item_id: Some(item.id),
+            seller_id: item.owner_id,
+            name: item.name.clone(),
+            unit_price: item.price,
+            quantity,
+        });
+    }
+
+    let mut line_totals = Vec::with_capacity(lines.len());
+    for line in &lines {
+        match line.unit_price.times(line.quantity) {
+            Some(total) => line_totals.push(total),
+            None => return Err(order_too_large(claims)),
+        }
+    }
+
+    // An order is charged in a single currency; items priced in different
+    // currencies have to be ordered separately.
+    let total = match Money::sum(line_totals.iter()) {
+        Some(total) => total,
+        None => {
+            tracing::warn!("Checkout with mixed currencies by {}", claims.sub);
+            return Err(AppError::Conflict(
+                "currency_mismatch",
+                "All items of an order must be priced in the same currency.".into(),
+            ));
+        }
+    };
+
+    // The total is stored in a DECIMAL(19,4) column as well.
+    if total.amount > MAX_AMOUNT {
+        return Err(order_too_large(claims));
+    }
+
+    Ok((lines, total))
+}
+
+fn order_too_large(claims: &Claims) -> AppError {
+    tracing::warn!("Checkout total too large by {}", claims.sub);
+    AppError::Validation(vec![FieldError::new(
+        "lines",
+        format!("order total must be at most {}", MAX_AMOUNT),
+    )])
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "orders",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "One page of orders of the caller, newest first", body = Page<OrderDetail>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_my_orders(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    OriginalUri(uri): OriginalUri,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<OrderDetail>>), AppError> {
+    tracing::info!("GET /orders for {}", claims.sub);
+
+    let page = paging.resolve(&state.config.pagination)?;
+    let details = state.orders.list_for_buyer(claims.uid, &page).await?;
+
+    Ok((pagination::links(&uri, details.next_cursor.as_deref()), Json(details)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "The order", body = OrderDetail),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_order(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<OrderDetail>, AppError> {
+    tracing::info!("GET /orders/{} for {}", id, claims.sub);
+
+    let detail = state.orders.get(id).await?;
+
+    // Someone else's order is reported as missing rather than forbidden,
+    // so order ids cannot be probed.
+    match detail {
+        Some(d) if d.order.user_id == claims.uid || claims.role == ADMIN_ROLE => Ok(Json(d)),
+        _ => {
+            tracing::warn!("Order {} not found for {}", id, claims.sub);
+            Err(AppError::NotFound(format!("Order {} not found.", id)))
+        }
+    }
+}
+
+/// Orders that contain at least one item of the calling seller, with only
+/// that seller's lines.
+#[utoipa::path(
+    get,
+    path = "/seller/orders",
+    tag = "orders",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "One page of orders with items of the caller", body = Page<OrderDetail>),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn get_seller_orders(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    OriginalUri(uri): OriginalUri,
+    Query(paging): Query<PageQuery>,
+) -> Result<(HeaderMap, Json<Page<OrderDetail>>), AppError> {
+    tracing::info!("GET /seller/orders for {}", claims.sub);
+
+    let page = paging.resolve(&state.config.pagination)?;
+    let details = state.orders.list_for_seller(claims.uid, &page).await?;
+
+    Ok((pagination::links(&uri, details.next_cursor.as_deref()), Json(details)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/orders/{id}/status",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id")),
+    request_body = UpdateOrderStatus,
+    responses(
+        (status = 200, description = "The order in its new status", body = OrderDetail),
+        (status = 400, description = "Invalid input", body = Problem),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn update_order_status(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<UpdateOrderStatus>,
+) -> Result<Json<OrderDetail>, AppError> {
+    tracing::info!(
+        "PATCH /orders/{}/status -> {} by {}",
+        id,
+        payload.status.as_str(),
+        claims.sub
+    );
+
+    let detail = transition_order(&state, &claims, id, payload.status).await?;
+
+    Ok(Json(detail))
+}
+
+#[utoipa::path(
+    post,
+    path = "/orders/{id}/cancel",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "The cancelled order", body = OrderDetail),
+        (status = 401, description = "Missing, invalid or revoked token", body = Problem),
+        (status = 403, description = "Not allowed for the caller", body = Problem),
+        (status = 404, description = "Not found", body = Problem),
+        (status = 409, description = "Conflicts with the current state", body = Problem)
+    ),
+    security(("bearer" = [])),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn cancel_order(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<OrderDetail>, AppError> {
+    tracing::info!("POST /orders/{}/cancel by {}", id, claims.sub);
+
+    let detail = transition_order(&state, &claims, id, OrderStatus::Cancelled).await?;
+
+    Ok(Json(detail))
+}
+
+/// Who may move an order from `current` into `next`. Admins record
+/// payments, since there is no payment step yet, and handle refunds,
+/// including cancelling a paid order. The buyer cancels while the order is
+/// pending. A seller ships and delivers only orders that hold nothing but
+/// their own items; orders with several sellers are left to admins.
+fn may_transition(
+    claims: &Claims,
+    order: &OrderAccess,
+    current: OrderStatus,
+    next: OrderStatus,
+) -> bool {
+    if claims.role == ADMIN_ROLE {
+        return true;
+    }
+
+    match next {
+        OrderStatus::Cancelled => order.user_id == claims.uid && current == OrderStatus::Pending,
+        OrderStatus::Shipped | OrderStatus::Delivered => order.only_seller_items,
+        OrderStatus::Pending | OrderStatus::Paid | OrderStatus::Refunded => false,
+    }
+}
+
+/// Moves an order into `next` if the state machine and the caller's role
+/// allow it, stamping the matching `*_at` column. Cancelling returns the
+/// ordered quantities to stock in the same transaction.
+async fn transition_order(
+    state: &AppState,
+    claims: &Claims,
+    id: i64,
+    next: OrderStatus,
+) -> Result<OrderDetail, AppError> {
+    let detail = state
+        .orders
+        .transition(id, claims.uid, next, &|order| check_transition(claims, id, order, next))
+        .await?;
+
+    tracing::info!("Order {} moved to {}", id, next.as_str());
+
+    Ok(detail)
+}
+
+/// Runs on the locked order before it changes status.
+fn check_transition(
+    claims: &Claims,
+    id: i64,
+    order: Option<&OrderAccess>,
+    next: OrderStatus,
+) -> Result<(), AppError> {
+    let is_admin = claims.role == ADMIN_ROLE;
+
+    let order = match order {
+        Some(o) if is_admin || o.user_id == claims.uid || o.has_seller_items => o,
+        _ => {
+            tracing::warn!("Order {} not found for {}", id, claims.sub);
+            return Err(AppError::NotFound(format!("Order {} not found.", id)));
+        }
+    };
+
+    let current = OrderStatus::parse(&order.status).ok_or_else(|| {
+        tracing::error!("Order {} has unknown status '{}'", id, order.status);
+        AppError::Internal("Order has an unknown status.".into())
+    })?;
+
+    if !current.can_transition_to(next) {
+        let allowed: Vec<&str> = current.next_states().iter().map(|s| s.as_str()).collect();
+
+        tracing::warn!(
+            "Illegal transition of order {} from {} to {}",
+            id,
+            current.as_str(),
+            next.as_str()
+        );
+        return Err(AppError::Conflict(
+            "illegal_transition",
+            if allowed.is_empty() {
+                format!(
+                    "Order {} is {} and cannot change anymore.",
+                    id,
+                    current.as_str()
+                )
+            } else {
+                format!(
+                    "Order {} cannot go from {} to {}; allowed next states: {}.",
+                    id,
+                    current.as_str(),
+                    next.as_str(),
+                    allowed.join(", ")
+                )
+            },
+        ));
+    }
+
+    if !may_transition(claims, order, current, next) {
+        tracing::warn!(
+            "{} may not move order {} to {}",
+            claims.sub,
+            id,
+            next.as_str()
+        );
+        return Err(AppError::Forbidden(format!(
+            "You may not mark order {} as {}.",
+            id,
+            next.as_str()
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Paging of list endpoints.
+//!
+//! Every list answers with a [`Page`]: one page of rows, the total and an
+//! opaque cursor for the next page, which the `Link` header repeats. A
+//! cursor holds the sort key and id of the last row, and the next page
+//! starts after that row (keyset paging), so rows added or removed further
+//! up do not shift later pages.
+
+use crate::config::PaginationConfig;
+use crate::error::{AppError, FieldError};
+use axum::http::{header, HeaderMap, HeaderValue, Uri};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// `next_cursor` of the previous page; omit it for the first page.
+    pub cursor: Option<String>,
+    /// Rows per page, up to the configured maximum (100 by default).
+    pub page_size: Option<u32>,
+}
+
+/// Position after the last row of a page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Order the cursor was issued for; see [`Cursor::key_for`].
+    #[serde(rename = "o")]
+    pub order: String,
+    /// Sort key of the row, `None` when the order is by id alone.
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(rename = "i")]
+    pub id: i64,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(raw: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(raw).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// The sort key, if the cursor was issued for `order`. A cursor from a
+    /// listing sorted differently would skip or repeat rows.
+    pub fn key_for(&self, order: &str) -> Result<Option<&str>, AppError> {
+        if self.order != order {
+            tracing::warn!("Cursor for order {} used with order {}", self.order, order);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "cursor",
+                "belongs to a listing with a different sort order",
+            )]));
+        }
+
+        Ok(self.key.as_deref())
+    }
+}
+
+/// What a store needs to fetch one page.
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub size: u32,
+    /// Start after this row; `None` starts at the first.
+    pub after: Option<Cursor>,
+    /// Rows skipped on top of the cursor. Only `/items/search` still takes a
+    /// `page` number.
+    pub offset: u64,
+}
+
+impl PageRequest {
+    /// Rows to fetch: one more than the page holds, to tell whether another
+    /// page follows.
+    pub fn fetch(&self) -> i64 {
+        self.size as i64 + 1
+    }
+}
+
+impl PageQuery {
+    /// Checks the page size against the configured bounds and decodes the
+    /// cursor.
+    pub fn resolve(&self, config: &PaginationConfig) -> Result<PageRequest, AppError> {
+        let mut errors = Vec::new();
+
+        let size = self.page_size.unwrap_or(config.default_page_size);
+        if !(1..=config.max_page_size).contains(&size) {
+            errors.push(FieldError::new(
+                "page_size",
+                format!("must be between 1 and {}", config.max_page_size),
+            ));
+        }
+
+        let after = match self.cursor.as_deref() {
+            Some(raw) => {
+                let cursor = Cursor::decode(raw);
+                if cursor.is_none() {
+                    errors.push(FieldError::new("cursor", "is not a cursor issued by this API"));
+                }
+                cursor
+            }
+            None => None,
+        };
+
+        if !errors.is_empty() {
+            tracing::warn!("Invalid paging parameters: {:?}", errors);
+            return Err(AppError::Validation(errors));
+        }
+
+        Ok(PageRequest {
+            size,
+            after,
+            offset: 0,
+        })
+    }
+}
+
+/// One page of a list.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Rows in the whole list, across all pages.
+    pub total: i64,
+    /// Pass as `cursor` to get the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from the rows a store fetched, each with its cursor, up
+    /// to [`PageRequest::fetch`] of them.
+    pub fn new(mut rows: Vec<(T, Cursor)>, total: i64, request: &PageRequest) -> Self {
+        let size = request.size as usize;
+
+        let next_cursor = if rows.len() > size {
+            rows.truncate(size);
+            rows.last().map(|(_, cursor)| cursor.encode())
+        } else {
+            None
+        };
+
+        Page {
+            items: rows.into_iter().map(|(row, _)| row).collect(),
+            total,
+            next_cursor,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// `Link` header pointing at the first page and, unless this is the last
+/// one, the next page of the list requested with `uri`.
+pub fn links(uri: &Uri, next_cursor: Option<&str>) -> HeaderMap {
+    // Every other parameter is kept as the client sent it.
+    let query: Vec<&str> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or("");
+            !pair.is_empty() && name != "cursor" && name != "page"
+        })
+        .collect();
+
+    let link = |extra: Option<String>| {
+        let mut params = query.clone();
+        let extra = extra.unwrap_or_default();
+        if !extra.is_empty() {
+            params.push(&extra);
+        }
+
+        if params.is_empty() {
+            format!("<{}>", uri.path())
+        } else {
+            format!("<{}?{}>", uri.path(), params.join("&"))
+        }
+    };
+
+    let mut value = format!("{}; rel=\"first\"", link(None));
+    if let Some(cursor) = next_cursor {
+        value.push_str(&format!(", {}; rel=\"next\"", link(Some(format!("cursor={}", cursor)))));
+    }
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(header::LINK, value);
+    }
+    headers
+}
@@ -0,0 +1,1054 @@
+use super::{
+    category_cycle, id_after, id_cursor, order_after, order_cursor, price_facets, CartRepository, CartRow,
+    CategoryRepository, CheckTransition, HealthRepository, ItemFilter, ItemOrder, ItemRepository,
+    NewItem, OrderAccess, OrderRepository, PlanOrder, PoolStats, RefreshToken, SortValue,
+    TokenState, UserRepository, PRICE_BUCKETS, UNSOLD_STATUSES,
+};
+use crate::error::AppError;
+use crate::models::{
+    Category, CategoryFacet, Item, ItemFacets, ItemSort, Order, OrderDetail, OrderLine, OrderStatus,
+    Role, SortOrder, UpdateItem, User,
+};
+use crate::money::Currency;
+use crate::pagination::{Page, PageRequest};
+use crate::search::SearchMode;
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+/// Backend that keeps everything in process memory. It enforces the same
+/// unique keys and cascades as the MySQL schema, so handlers behave the same
+/// on both.
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+struct StoredRefreshToken {
+    token_hash: String,
+    token: RefreshToken,
+}
+
+struct CartEntry {
+    quantity: i32,
+    unit_price: Decimal,
+    /// Insertion sequence, stands in for `added_at`.
+    added: i64,
+}
+
+#[derive(Default)]
+struct Data {
+    next_id: i64,
+    categories: BTreeMap<i64, Category>,
+    items: BTreeMap<i64, Item>,
+    roles: Vec<Role>,
+    users: BTreeMap<i64, User>,
+    refresh_tokens: Vec<StoredRefreshToken>,
+    revoked_tokens: HashMap<String, NaiveDateTime>,
+    /// Keyed by `(user_id, item_id)`.
+    carts: BTreeMap<(i64, i64), CartEntry>,
+    orders: BTreeMap<i64, Order>,
+    /// `(order_id, line)` in insertion order.
+    order_lines: Vec<(i64, OrderLine)>,
+}
+
+impl Data {
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// The child of `parent_id` with this name, compared case-insensitively
+    /// like the MySQL collation.
+    fn child_named(&self, parent_id: Option<i64>, name: &str) -> Option<&Category> {
+        let name = name.to_lowercase();
+
+        self.categories
+            .values()
+            .find(|c| c.parent_id == parent_id && c.name.to_lowercase() == name)
+    }
+
+    /// The category and its ancestors, top-level first.
+    fn category_path(&self, id: i64) -> Vec<Category> {
+        let mut path: Vec<Category> = Vec::new();
+        let mut next = Some(id);
+        while let Some(category) = next.and_then(|id| self.categories.get(&id)) {
+            // Guard against a loop in the parents, however it came about.
+            if path.iter().any(|c| c.id == category.id) {
+                break;
+            }
+            path.push(category.clone());
+            next = category.parent_id;
+        }
+        path.reverse();
+
+        path
+    }
+
+    /// Units of the item in orders that still count as sold.
+    fn units_sold(&self, item_id: i64) -> i64 {
+        self.order_lines
+            .iter()
+            .filter(|(order_id, line)| {
+                line.item_id == Some(item_id)
+                    && self.orders.get(order_id).is_some_and(|o| {
+                        !UNSOLD_STATUSES.iter().any(|s| s.as_str() == o.status)
+                    })
+            })
+            .map(|(_, line)| line.quantity as i64)
+            .sum()
+    }
+
+    fn order_detail(&self, id: i64, seller_id: Option<i64>) -> Option<OrderDetail> {
+        let order = self.orders.get(&id)?.clone();
+        let lines = self
+            .order_lines
+            .iter()
+            .filter(|(order_id, l)| *order_id == id && seller_id.is_none_or(|s| l.seller_id == Some(s)))
+            .map(|(_, l)| l.clone())
+            .collect();
+
+        Some(OrderDetail { order, lines })
+    }
+
+    /// One page of the orders in `ids`, newest first like
+    /// `ORDER BY created_at DESC, id DESC`.
+    fn order_page(&self, mut ids: Vec<i64>, seller_id: Option<i64>, page: &PageRequest) -> Result<Page<OrderDetail>, AppError> {
+        let after = order_after(page)?;
+
+        ids.sort_by_key(|id| {
+            let order = &self.orders[id];
+            Reverse((order.created_at, order.id))
+        });
+
+        let total = ids.len() as i64;
+        let rows = ids
+            .into_iter()
+            .filter(|id| {
+                let order = &self.orders[id];
+                after.is_none_or(|after| (order.created_at, order.id) < after)
+            })
+            .take(page.fetch() as usize)
+            .filter_map(|id| self.order_detail(id, seller_id))
+            .map(|detail| {
+                let cursor = order_cursor(&detail.order);
+                (detail, cursor)
+            })
+            .collect();
+
+        Ok(Page::new(rows, total, page))
+    }
+}
+
+impl MemoryStore {
+    /// An empty store with the roles the migrations seed.
+    pub fn new() -> Self {
+        let mut data = Data::default();
+
+        for name in ["seller", "customer", "admin"] {
+            let id = data.next_id();
+            data.roles.push(Role {
+                id,
+                name: name.to_string(),
+            });
+        }
+
+        Self {
+            data: Mutex::new(data),
+        }
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        // Every change under the lock is a handful of plain assignments, so
+        // the data is still usable after a panic elsewhere.
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+fn duplicate() -> AppError {
+    AppError::Conflict(
+        "duplicate",
+        "A record with the same unique value already exists.".into(),
+    )
+}
+
+fn constraint_violation() -> AppError {
+    AppError::Conflict(
+        "constraint_violation",
+        "The request conflicts with related records.".into(),
+    )
+}
+
+/// Facet whose own filter is left out while counting it.
+#[derive(PartialEq)]
+enum Facet {
+    None,
+    Category,
+    Price,
+}
+
+fn search_words(filter: &ItemFilter) -> Option<SearchMode> {
+    filter.search.as_deref().map(SearchMode::parse)
+}
+
+/// Whether `item` passes `filter`, with its relevance for the search words.
+/// Stands in for MySQL's ranking with the number of word prefix hits.
+fn item_matches(item: &Item, filter: &ItemFilter, search: &Option<SearchMode>, skip: Facet) -> Option<usize> {
+    let score = match search {
+        Some(SearchMode::Words(words)) => {
+            let text = format!("{} {}", item.name, item.description.as_deref().unwrap_or("")).to_lowercase();
+            let item_words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric()).collect();
+
+            let mut score = 0;
+            for word in words {
+                let n = item_words.iter().filter(|w| w.starts_with(word.as_str())).count();
+                if n == 0 {
+                    return None;
+                }
+                score += n;
+            }
+            score
+        }
+        Some(SearchMode::Substring(text)) => {
+            // MySQL's default collation compares case-insensitively.
+            let needle = text.to_lowercase();
+            let found = item.name.to_lowercase().contains(&needle)
+                || item
+                    .description
+                    .as_deref()
+                    .is_some_and(|d| d.to_lowercase().contains(&needle));
+            if !found {
+                return None;
+            }
+            0
+        }
+        None => 0,
+    };
+
+    let amount = item.price.amount;
+    let passes = (skip == Facet::Price
+        || (filter.min_price.is_none_or(|min| amount >= min)
+            && filter.max_price.is_none_or(|max| amount <= max)))
+        && filter.currency.is_none_or(|c| item.price.currency == c)
+        && (!filter.in_stock || item.quantity > 0)
+        && filter.owner_id.is_none_or(|owner| item.owner_id == Some(owner))
+        && (skip == Facet::Category
+            || filter.category_ids.is_empty()
+            || item.category_id.is_some_and(|id| filter.category_ids.contains(&id)));
+
+    passes.then_some(score)
+}
+
+fn directed(ordering: Ordering, direction: SortOrder) -> Ordering {
+    match direction {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    }
+}
+
+fn compare_sort_values(a: &SortValue, b: &SortValue) -> Ordering {
+    match (a, b) {
+        (SortValue::Decimal(a), SortValue::Decimal(b)) => a.cmp(b),
+        // MySQL's default collation compares case-insensitively.
+        (SortValue::Text(a), SortValue::Text(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        (SortValue::Time(a), SortValue::Time(b)) => a.cmp(b),
+        (SortValue::Count(a), SortValue::Count(b)) => a.cmp(b),
+        (SortValue::Score(a), SortValue::Score(b)) => a.total_cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+#[async_trait]
+impl ItemRepository for MemoryStore {
+    async fn browse(&self, filter: &ItemFilter, order: ItemOrder, page: &PageRequest) -> Result<Page<Item>, AppError> {
+        let after = order.after(page)?;
+        let data = self.data();
+        let words = search_words(filter);
+
+        let mut rows: Vec<(Option<SortValue>, &Item)> = data
+            .items
+            .values()
+            .filter_map(|item| {
+                let score = item_matches(item, filter, &words, Facet::None)?;
+                let key = match order {
+                    ItemOrder::Id => None,
+                    ItemOrder::Relevance => Some(SortValue::Score(score as f64)),
+                    ItemOrder::Sorted(ItemSort::Price, _) => Some(SortValue::Decimal(item.price.amount)),
+                    ItemOrder::Sorted(ItemSort::Name, _) => Some(SortValue::Text(item.name.clone())),
+                    ItemOrder::Sorted(ItemSort::Newest, _) => Some(SortValue::Time(item.created_at)),
+                    ItemOrder::Sorted(ItemSort::Popularity, _) => Some(SortValue::Count(data.units_sold(item.id))),
+                };
+                Some((key, item))
+            })
+            .collect();
+
+        let total = rows.len() as i64;
+
+        let compare = |a: (&Option<SortValue>, i64), b: (&Option<SortValue>, i64)| {
+            let (key_direction, id_direction) = match order {
+                ItemOrder::Id => (SortOrder::Asc, SortOrder::Asc),
+                ItemOrder::Relevance => (SortOrder::Desc, SortOrder::Asc),
+                ItemOrder::Sorted(_, direction) => (direction, direction),
+            };
+            let by_key = match (a.0, b.0) {
+                (Some(a), Some(b)) => compare_sort_values(a, b),
+                _ => Ordering::Equal,
+            };
+            directed(by_key, key_direction).then(directed(a.1.cmp(&b.1), id_direction))
+        };
+
+        rows.sort_by(|(a_key, a), (b_key, b)| compare((a_key, a.id), (b_key, b.id)));
+
+        let rows = rows
+            .into_iter()
+            .filter(|(key, item)| match &after {
+                Some((after_key, after_id)) => compare((key, item.id), (after_key, *after_id)) == Ordering::Greater,
+                None => true,
+            })
+            .skip(page.offset as usize)
+            .take(page.fetch() as usize)
+            .map(|(key, item)| (item.clone(), order.cursor(key.as_ref(), item.id)))
+            .collect();
+
+        Ok(Page::new(rows, total, page))
+    }
+
+    async fn facets(&self, filter: &ItemFilter) -> Result<ItemFacets, AppError> {
+        let data = self.data();
+        let words = search_words(filter);
+
+        let mut per_category: BTreeMap<Option<i64>, i64> = BTreeMap::new();
+        for item in data.items.values() {
+            if item_matches(item, filter, &words, Facet::Category).is_some() {
+                *per_category.entry(item.category_id).or_default() += 1;
+            }
+        }
+
+        let mut categories: Vec<CategoryFacet> = per_category
+            .into_iter()
+            .map(|(category_id, count)| CategoryFacet {
+                category_id,
+                name: category_id
+                    .and_then(|id| data.categories.get(&id))
+                    .map(|c| c.name.clone()),
+                count,
+            })
+            .collect();
+        // Stable, so equal counts stay in category id order.
+        categories.sort_by_key(|c| Reverse(c.count));
+
+        if filter.currency.is_none() {
+            return Ok(ItemFacets {
+                categories,
+                price: Vec::new(),
+            });
+        }
+
+        let mut per_bucket = [0; PRICE_BUCKETS.len()];
+        for item in data.items.values() {
+            if item_matches(item, filter, &words, Facet::Price).is_some() {
+                let bucket = PRICE_BUCKETS
+                    .iter()
+                    .rposition(|min| item.price.amount >= Decimal::from(*min))
+                    .unwrap_or(0);
+                per_bucket[bucket] += 1;
+            }
+        }
+
+        Ok(ItemFacets {
+            categories,
+            price: price_facets(|bucket| per_bucket[bucket]),
+        })
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<Item>, AppError> {
+        Ok(self.data().items.get(&id).cloned())
+    }
+
+    async fn low_stock(&self, threshold: i32, limit: i64) -> Result<Vec<Item>, AppError> {
+        let mut items: Vec<Item> = self
+            .data()
+            .items
+            .values()
+            .filter(|i| i.quantity <= threshold)
+            .cloned()
+            .collect();
+
+        items.sort_by_key(|i| (i.quantity, i.id));
+        items.truncate(limit.max(0) as usize);
+
+        Ok(items)
+    }
+
+    async fn create(&self, item: NewItem) -> Result<Item, AppError> {
+        let mut data = self.data();
+
+        if let Some(category_id) = item.category_id {
+            if !data.categories.contains_key(&category_id) {
+                return Err(constraint_violation());
+            }
+        }
+
+        let item = Item {
+            id: data.next_id(),
+            name: item.name,
+            description: item.description,
+            price: item.price,
+            quantity: item.quantity,
+            category_id: item.category_id,
+            owner_id: item.owner_id,
+            created_at: now(),
+        };
+        data.items.insert(item.id, item.clone());
+
+        Ok(item)
+    }
+
+    async fn update(&self, id: i64, changes: &UpdateItem) -> Result<Option<Item>, AppError> {
+        let mut data = self.data();
+
+        if let Some(Some(category_id)) = changes.category_id {
+            if !data.categories.contains_key(&category_id) {
+                return Err(constraint_violation());
+            }
+        }
+
+        let Some(stored) = data.items.get_mut(&id) else {
+            return Ok(None);
+        };
+
+        if let Some(name) = &changes.name {
+            stored.name = name.clone();
+        }
+        if let Some(description) = &changes.description {
+            stored.description = description.clone();
+        }
+        if let Some(price) = changes.price {
+            stored.price = price;
+        }
+        if let Some(quantity) = changes.quantity {
+            stored.quantity = quantity;
+        }
+        if let Some(category_id) = changes.category_id {
+            stored.category_id = category_id;
+        }
+
+        Ok(Some(stored.clone()))
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), AppError> {
+        let mut data = self.data();
+
+        data.items.remove(&id);
+        // `ON DELETE CASCADE` on carts, `ON DELETE SET NULL` on order lines.
+        data.carts.retain(|(_, item_id), _| *item_id != id);
+        for (_, line) in data.order_lines.iter_mut() {
+            if line.item_id == Some(id) {
+                line.item_id = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CategoryRepository for MemoryStore {
+    async fn list(&self, page: &PageRequest) -> Result<Page<Category>, AppError> {
+        let after = id_after(page)?.unwrap_or(0);
+        let data = self.data();
+
+        let rows = data
+            .categories
+            .range(after + 1..)
+            .take(page.fetch() as usize)
+            .map(|(id, c)| (c.clone(), id_cursor(*id)))
+            .collect();
+
+        Ok(Page::new(rows, data.categories.len() as i64, page))
+    }
+
+    async fn all(&self) -> Result<Vec<Category>, AppError> {
+        Ok(self.data().categories.values().cloned().collect())
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<Category>, AppError> {
+        Ok(self.data().categories.get(&id).cloned())
+    }
+
+    async fn find_by_name(&self, name: &str) -> Result<Option<Category>, AppError> {
+        Ok(self
+            .data()
+            .categories
+            .values()
+            .find(|c| c.name == name)
+            .cloned())
+    }
+
+    async fn find_child(&self, parent_id: Option<i64>, name: &str) -> Result<Option<Category>, AppError> {
+        Ok(self.data().child_named(parent_id, name).cloned())
+    }
+
+    async fn ancestors(&self, id: i64) -> Result<Vec<Category>, AppError> {
+        Ok(self.data().category_path(id))
+    }
+
+    async fn descendant_ids(&self, ids: &[i64]) -> Result<Vec<i64>, AppError> {
+        let data = self.data();
+
+        let mut found: BTreeSet<i64> = ids
+            .iter()
+            .copied()
+            .filter(|id| data.categories.contains_key(id))
+            .collect();
+        let mut pending: Vec<i64> = found.iter().copied().collect();
+        while let Some(parent) = pending.pop() {
+            for child in data.categories.values().filter(|c| c.parent_id == Some(parent)) {
+                if found.insert(child.id) {
+                    pending.push(child.id);
+                }
+            }
+        }
+
+        Ok(found.into_iter().collect())
+    }
+
+    async fn create(&self, name: &str, parent_id: Option<i64>) -> Result<Category, AppError> {
+        let mut data = self.data();
+
+        if parent_id.is_some_and(|id| !data.categories.contains_key(&id)) {
+            return Err(constraint_violation());
+        }
+        if data.child_named(parent_id, name).is_some() {
+            return Err(duplicate());
+        }
+
+        let category = Category {
+            id: data.next_id(),
+            parent_id,
+            name: name.to_string(),
+        };
+        data.categories.insert(category.id, category.clone());
+
+        Ok(category)
+    }
+
+    async fn update(&self, category: &Category) -> Result<(), AppError> {
+        let mut data = self.data();
+
+        if category.parent_id.is_some_and(|id| !data.categories.contains_key(&id)) {
+            return Err(constraint_violation());
+        }
+        if data
+            .child_named(category.parent_id, &category.name)
+            .is_some_and(|c| c.id != category.id)
+        {
+            return Err(duplicate());
+        }
+        if let Some(parent_id) = category.parent_id {
+            if data.category_path(parent_id).iter().any(|c| c.id == category.id) {
+                return Err(category_cycle());
+            }
+        }
+        if let Some(existing) = data.categories.get_mut(&category.id) {
+            *existing = category.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), AppError> {
+        let mut data = self.data();
+
+        if data.items.values().any(|i| i.category_id == Some(id))
+            || data.categories.values().any(|c| c.parent_id == Some(id))
+        {
+            return Err(constraint_violation());
+        }
+        data.categories.remove(&id);
+
+        Ok(())
+    }
+
+    async fn has_items(&self, id: i64) -> Result<bool, AppError> {
+        Ok(self.data().items.values().any(|i| i.category_id == Some(id)))
+    }
+
+    async fn has_children(&self, id: i64) -> Result<bool, AppError> {
+        Ok(self.data().categories.values().any(|c| c.parent_id == Some(id)))
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryStore {
+    async fn find_role(&self, name: &str) -> Result<Option<Role>, AppError> {
+        Ok(self.data().roles.iter().find(|r| r.name == name).cloned())
+    }
+
+    async fn role_name(&self, role_id: i64) -> Result<String, AppError> {
+        self.data()
+            .roles
+            .iter()
+            .find(|r| r.id == role_id)
+            .map(|r| r.name.clone())
+            .ok_or_else(|| AppError::NotFound("Resource not found.".into()))
+    }
+
+    async fn create(&self, username: &str, password_hash: &str, role_id: i64) -> Result<User, AppError> {
+        let mut data = self.data();
+
+        if data.users.values().any(|u| u.username == username) {
+            return Err(duplicate());
+        }
+        if !data.roles.iter().any(|r| r.id == role_id) {
+            return Err(constraint_violation());
+        }
+
+        let user = User {
+            id: data.next_id(),
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            role_id,
+            token_version: 0,
+        };
+        data.users.insert(user.id, user.clone());
+
+        Ok(user)
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<User>, AppError> {
+        Ok(self.data().users.get(&id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        Ok(self
+            .data()
+            .users
+            .values()
+            .find(|u| u.username == username)
+            .cloned())
+    }
+
+    async fn set_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        if let Some(user) = self.data().users.get_mut(&id) {
+            user.password_hash = password_hash.to_string();
+        }
+
+        Ok(())
+    }
+
+    async fn set_role(&self, id: i64, role_id: i64) -> Result<bool, AppError> {
+        let mut data = self.data();
+
+        if !data.roles.iter().any(|r| r.id == role_id) {
+            return Err(constraint_violation());
+        }
+
+        match data.users.get_mut(&id) {
+            Some(user) => {
+                user.role_id = role_id;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn token_state(&self, user_id: i64, jti: &str) -> Result<Option<TokenState>, AppError> {
+        let data = self.data();
+
+        let Some(user) = data.users.get(&user_id) else {
+            return Ok(None);
+        };
+        let role = data
+            .roles
+            .iter()
+            .find(|r| r.id == user.role_id)
+            .map(|r| r.name.clone())
+            .unwrap_or_default();
+
+        Ok(Some(TokenState {
+            token_version: user.token_version,
+            role,
+            revoked: data.revoked_tokens.contains_key(jti),
+        }))
+    }
+
+    async fn store_refresh_token(
+        &self,
+        user_id: i64,
+        family_id: &str,
+        token_hash: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), AppError> {
+        let mut data = self.data();
+
+        if data.refresh_tokens.iter().any(|t| t.token_hash == token_hash) {
+            return Err(duplicate());
+        }
+
+        let id = data.next_id();
+        data.refresh_tokens.push(StoredRefreshToken {
+            token_hash: token_hash.to_string(),
+            token: RefreshToken {
+                id,
+                user_id,
+                family_id: family_id.to_string(),
+                expires_at,
+                used_at: None,
+                revoked_at: None,
+            },
+        });
+
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        Ok(self
+            .data()
+            .refresh_tokens
+            .iter()
+            .find(|t| t.token_hash == token_hash)
+            .map(|t| t.token.clone()))
+    }
+
+    async fn use_refresh_token(&self, id: i64) -> Result<bool, AppError> {
+        let mut data = self.data();
+
+        let token = data
+            .refresh_tokens
+            .iter_mut()
+            .map(|t| &mut t.token)
+            .find(|t| t.id == id && t.used_at.is_none() && t.revoked_at.is_none());
+
+        match token {
+            Some(token) => {
+                token.used_at = Some(now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AppError> {
+        let now = now();
+
+        for stored in self.data().refresh_tokens.iter_mut() {
+            if stored.token.family_id == family_id && stored.token.revoked_at.is_none() {
+                stored.token.revoked_at = Some(now);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn deny_access_token(&self, jti: &str, expires_at: NaiveDateTime) -> Result<(), AppError> {
+        let now = now();
+        let mut data = self.data();
+
+        data.revoked_tokens.entry(jti.to_string()).or_insert(expires_at);
+        data.revoked_tokens.retain(|_, expires_at| *expires_at >= now);
+
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(&self, user_id: i64) -> Result<(), AppError> {
+        let now = now();
+        let mut data = self.data();
+
+        if let Some(user) = data.users.get_mut(&user_id) {
+            user.token_version += 1;
+        }
+        for stored in data.refresh_tokens.iter_mut() {
+            if stored.token.user_id == user_id && stored.token.revoked_at.is_none() {
+                stored.token.revoked_at = Some(now);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CartRepository for MemoryStore {
+    async fn lines(&self, user_id: i64) -> Result<Vec<CartRow>, AppError> {
+        let data = self.data();
+
+        let mut entries: Vec<(&i64, &CartEntry)> = data
+            .carts
+            .iter()
+            .filter(|((user, _), _)| *user == user_id)
+            .map(|((_, item_id), entry)| (item_id, entry))
+            .collect();
+        entries.sort_by_key(|(item_id, entry)| (entry.added, **item_id));
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(item_id, entry)| {
+                let item = data.items.get(item_id)?;
+
+                Some(CartRow {
+                    item_id: *item_id,
+                    name: item.name.clone(),
+                    quantity: entry.quantity,
+                    stored_price: entry.unit_price,
+                    current_price: item.price.amount,
+                    currency: item.price.currency,
+                    available: item.quantity,
+                })
+            })
+            .collect())
+    }
+
+    async fn reprice(&self, user_id: i64) -> Result<(), AppError> {
+        let mut data = self.data();
+        let Data { carts, items, .. } = &mut *data;
+
+        for ((user, item_id), entry) in carts.iter_mut() {
+            if *user != user_id {
+                continue;
+            }
+            if let Some(item) = items.get(item_id) {
+                entry.unit_price = item.price.amount;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn quantity(&self, user_id: i64, item_id: i64) -> Result<Option<i32>, AppError> {
+        Ok(self.data().carts.get(&(user_id, item_id)).map(|e| e.quantity))
+    }
+
+    async fn currency(&self, user_id: i64, except_item_id: i64) -> Result<Option<Currency>, AppError> {
+        let data = self.data();
+
+        Ok(data
+            .carts
+            .keys()
+            .filter(|(user, item_id)| *user == user_id && *item_id != except_item_id)
+            .find_map(|(_, item_id)| data.items.get(item_id))
+            .map(|item| item.price.currency))
+    }
+
+    async fn put(&self, user_id: i64, item_id: i64, quantity: i32, unit_price: Decimal) -> Result<(), AppError> {
+        let mut data = self.data();
+
+        if !data.items.contains_key(&item_id) || !data.users.contains_key(&user_id) {
+            return Err(constraint_violation());
+        }
+
+        let added = data.next_id();
+        data.carts
+            .entry((user_id, item_id))
+            .and_modify(|e| {
+                e.quantity = quantity;
+                e.unit_price = unit_price;
+            })
+            .or_insert(CartEntry {
+                quantity,
+                unit_price,
+                added,
+            });
+
+        Ok(())
+    }
+
+    async fn set_quantity(&self, user_id: i64, item_id: i64, quantity: i32) -> Result<(), AppError> {
+        if let Some(entry) = self.data().carts.get_mut(&(user_id, item_id)) {
+            entry.quantity = quantity;
+        }
+
+        Ok(())
+    }
+
+    async fn remove(&self, user_id: i64, item_id: i64) -> Result<bool, AppError> {
+        Ok(self.data().carts.remove(&(user_id, item_id)).is_some())
+    }
+
+    async fn clear(&self, user_id: i64) -> Result<(), AppError> {
+        self.data().carts.retain(|(user, _), _| *user != user_id);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MemoryStore {
+    async fn checkout(
+        &self,
+        user_id: i64,
+        lines: Option<BTreeMap<i64, i32>>,
+        plan: &PlanOrder<'_>,
+    ) -> Result<OrderDetail, AppError> {
+        // Holding the lock for the whole checkout makes it atomic.
+        let mut data = self.data();
+
+        let from_cart = lines.is_none();
+        let requested = match lines {
+            Some(lines) => lines,
+            None => data
+                .carts
+                .iter()
+                .filter(|((user, _), _)| *user == user_id)
+                .map(|((_, item_id), entry)| (*item_id, entry.quantity))
+                .collect(),
+        };
+
+        let locked: Vec<(i64, i32, Option<Item>)> = requested
+            .into_iter()
+            .map(|(item_id, quantity)| (item_id, quantity, data.items.get(&item_id).cloned()))
+            .collect();
+
+        let (lines, total) = plan(&locked)?;
+
+        for line in &lines {
+            if let Some(item) = line.item_id.and_then(|id| data.items.get_mut(&id)) {
+                item.quantity -= line.quantity;
+            }
+        }
+
+        let order = Order {
+            id: data.next_id(),
+            user_id,
+            status: OrderStatus::Pending.as_str().to_string(),
+            total,
+            created_at: now(),
+            paid_at: None,
+            shipped_at: None,
+            delivered_at: None,
+            cancelled_at: None,
+            refunded_at: None,
+        };
+        let order_id = order.id;
+
+        data.orders.insert(order_id, order);
+        data.order_lines
+            .extend(lines.into_iter().map(|line| (order_id, line)));
+
+        if from_cart {
+            data.carts.retain(|(user, _), _| *user != user_id);
+        }
+
+        Ok(data
+            .order_detail(order_id, None)
+            .expect("order was just inserted"))
+    }
+
+    async fn list_for_buyer(&self, user_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError> {
+        let data = self.data();
+
+        let ids = data
+            .orders
+            .values()
+            .filter(|o| o.user_id == user_id)
+            .map(|o| o.id)
+            .collect();
+
+        data.order_page(ids, None, page)
+    }
+
+    async fn list_for_seller(&self, seller_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError> {
+        let data = self.data();
+
+        let mut ids: Vec<i64> = data
+            .order_lines
+            .iter()
+            .filter(|(_, l)| l.seller_id == Some(seller_id))
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        ids.dedup();
+
+        data.order_page(ids, Some(seller_id), page)
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<OrderDetail>, AppError> {
+        Ok(self.data().order_detail(id, None))
+    }
+
+    async fn transition(
+        &self,
+        id: i64,
+        caller_id: i64,
+        next: OrderStatus,
+        check: &CheckTransition<'_>,
+    ) -> Result<OrderDetail, AppError> {
+        let mut data = self.data();
+
+        let access = data.orders.get(&id).map(|o| {
+            let mut sellers = data
+                .order_lines
+                .iter()
+                .filter(|(order_id, _)| *order_id == id)
+                .map(|(_, l)| l.seller_id);
+
+            OrderAccess {
+                user_id: o.user_id,
+                status: o.status.clone(),
+                has_seller_items: sellers.clone().any(|s| s == Some(caller_id)),
+                only_seller_items: sellers.clone().next().is_some()
+                    && sellers.all(|s| s == Some(caller_id)),
+            }
+        });
+
+        check(access.as_ref())?;
+
+        if next == OrderStatus::Cancelled {
+            let Data {
+                items, order_lines, ..
+            } = &mut *data;
+
+            for (_, line) in order_lines.iter().filter(|(order_id, _)| *order_id == id) {
+                if let Some(item) = line.item_id.and_then(|item_id| items.get_mut(&item_id)) {
+                    item.quantity += line.quantity;
+                }
+            }
+        }
+
+        let now = now();
+        if let Some(order) = data.orders.get_mut(&id) {
+            order.status = next.as_str().to_string();
+
+            let stamp = match next {
+                OrderStatus::Pending => None,
+                OrderStatus::Paid => Some(&mut order.paid_at),
+                OrderStatus::Shipped => Some(&mut order.shipped_at),
+                OrderStatus::Delivered => Some(&mut order.delivered_at),
+                OrderStatus::Cancelled => Some(&mut order.cancelled_at),
+                OrderStatus::Refunded => Some(&mut order.refunded_at),
+            };
+            if let Some(stamp) = stamp {
+                *stamp = Some(now);
+            }
+        }
+
+        data.order_detail(id, None)
+            .ok_or_else(|| AppError::NotFound(format!("Order {} not found.", id)))
+    }
+}
+
+#[async_trait]
+impl HealthRepository for MemoryStore {
+    async fn ping(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Option<usize>, AppError> {
+        Ok(Some(0))
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
@@ -0,0 +1,468 @@
+//! Storage behind traits, so handlers do not talk to MySQL directly.
+//!
+//! [`MySqlStore`] is the production backend. [`MemoryStore`] keeps everything
+//! in process memory and lets the router run in tests without a database.
+//! Both report failures as [`AppError`], the way handlers already did.
+
+mod memory;
+mod mysql;
+
+pub use memory::MemoryStore;
+pub use mysql::MySqlStore;
+
+use crate::error::{AppError, FieldError};
+use crate::models::{
+    Category, Item, ItemFacets, ItemSort, Order, OrderDetail, OrderLine, OrderStatus, PriceFacet,
+    Role, SortOrder, UpdateItem, User,
+};
+use crate::pagination::{Cursor, Page, PageRequest};
+use crate::search::SearchMode;
+use crate::money::{Currency, Money};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use sqlx::FromRow;
+use std::collections::BTreeMap;
+
+/// Fields of an item that is about to be inserted.
+#[derive(Debug)]
+pub struct NewItem {
+    pub name: String,
+    pub description: Option<String>,
+    pub price: Money,
+    pub quantity: i32,
+    pub category_id: Option<i64>,
+    pub owner_id: Option<i64>,
+}
+
+/// Filters of the item listing. Unset fields do not filter.
+#[derive(Debug, Default, Clone)]
+pub struct ItemFilter {
+    /// Search text, see [`crate::search`].
+    pub search: Option<String>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub currency: Option<Currency>,
+    pub in_stock: bool,
+    /// Items in any of these categories.
+    pub category_ids: Vec<i64>,
+    pub owner_id: Option<i64>,
+}
+
+/// Lower bounds of the price facet buckets; the last bucket is open-ended.
+pub const PRICE_BUCKETS: [i64; 5] = [0, 10, 25, 50, 100];
+
+/// Statuses whose orders do not count towards popularity.
+pub const UNSOLD_STATUSES: [OrderStatus; 2] = [OrderStatus::Cancelled, OrderStatus::Refunded];
+
+/// The price facet, given the count of each bucket by index.
+fn price_facets(count: impl Fn(usize) -> i64) -> Vec<PriceFacet> {
+    PRICE_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(bucket, min)| PriceFacet {
+            min: Decimal::from(*min),
+            max: PRICE_BUCKETS.get(bucket + 1).map(|max| Decimal::from(*max)),
+            count: count(bucket),
+        })
+        .collect()
+}
+
+/// Order of an item listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemOrder {
+    /// Oldest first.
+    Id,
+    /// Best search matches first, ties oldest first.
+    Relevance,
+    /// Ties in the same direction as the key.
+    Sorted(ItemSort, SortOrder),
+}
+
+impl ItemOrder {
+    /// The requested sort; otherwise relevance when there are search words.
+    pub fn resolve(filter: &ItemFilter, sort: Option<(ItemSort, SortOrder)>) -> Self {
+        match sort {
+            Some((key, order)) => ItemOrder::Sorted(key, order),
+            None => match filter.search.as_deref().map(SearchMode::parse) {
+                Some(SearchMode::Words(_)) => ItemOrder::Relevance,
+                _ => ItemOrder::Id,
+            },
+        }
+    }
+
+    /// Names the order in cursors.
+    pub fn tag(self) -> &'static str {
+        match self {
+            ItemOrder::Id => "id",
+            ItemOrder::Relevance => "relevance",
+            ItemOrder::Sorted(ItemSort::Price, SortOrder::Asc) => "price.asc",
+            ItemOrder::Sorted(ItemSort::Price, SortOrder::Desc) => "price.desc",
+            ItemOrder::Sorted(ItemSort::Name, SortOrder::Asc) => "name.asc",
+            ItemOrder::Sorted(ItemSort::Name, SortOrder::Desc) => "name.desc",
+            ItemOrder::Sorted(ItemSort::Newest, SortOrder::Asc) => "newest.asc",
+            ItemOrder::Sorted(ItemSort::Newest, SortOrder::Desc) => "newest.desc",
+            ItemOrder::Sorted(ItemSort::Popularity, SortOrder::Asc) => "popularity.asc",
+            ItemOrder::Sorted(ItemSort::Popularity, SortOrder::Desc) => "popularity.desc",
+        }
+    }
+
+    /// Where the page starts: the sort key and id of the row before it.
+    pub fn after(self, page: &PageRequest) -> Result<Option<(Option<SortValue>, i64)>, AppError> {
+        let Some(cursor) = &page.after else {
+            return Ok(None);
+        };
+
+        let key = match (self, cursor.key_for(self.tag())?) {
+            (ItemOrder::Id, None) => None,
+            (ItemOrder::Id, Some(_)) => return Err(invalid_cursor()),
+            (_, None) => return Err(invalid_cursor()),
+            (ItemOrder::Relevance, Some(raw)) => Some(SortValue::Score(raw.parse().map_err(|_| invalid_cursor())?)),
+            (ItemOrder::Sorted(key, _), Some(raw)) => Some(match key {
+                ItemSort::Price => SortValue::Decimal(raw.parse().map_err(|_| invalid_cursor())?),
+                ItemSort::Name => SortValue::Text(raw.to_owned()),
+                ItemSort::Newest => SortValue::Time(
+                    NaiveDateTime::parse_from_str(raw, CURSOR_TIME_FORMAT).map_err(|_| invalid_cursor())?,
+                ),
+                ItemSort::Popularity => SortValue::Count(raw.parse().map_err(|_| invalid_cursor())?),
+            }),
+        };
+
+        Ok(Some((key, cursor.id)))
+    }
+
+    /// Cursor pointing after a row with this key and id.
+    pub fn cursor(self, key: Option<&SortValue>, id: i64) -> Cursor {
+        Cursor {
+            order: self.tag().to_owned(),
+            key: key.map(SortValue::to_string),
+            id,
+        }
+    }
+}
+
+/// Sort key of a row, as kept in cursors.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Decimal(Decimal),
+    Text(String),
+    Time(NaiveDateTime),
+    Count(i64),
+    Score(f64),
+}
+
+impl std::fmt::Display for SortValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortValue::Decimal(d) => write!(f, "{}", d),
+            SortValue::Text(s) => f.write_str(s),
+            SortValue::Time(t) => write!(f, "{}", t.format(CURSOR_TIME_FORMAT)),
+            SortValue::Count(n) => write!(f, "{}", n),
+            // Prints the shortest text that parses back to the same value.
+            SortValue::Score(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+const CURSOR_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+fn invalid_cursor() -> AppError {
+    AppError::Validation(vec![FieldError::new("cursor", "is not a cursor issued by this API")])
+}
+
+/// A move of a category under itself or one of its subcategories.
+fn category_cycle() -> AppError {
+    AppError::Validation(vec![FieldError::new(
+        "parent_id",
+        "must not be the category itself or one of its subcategories",
+    )])
+}
+
+/// Cursor of an order list, newest first.
+pub fn order_cursor(order: &Order) -> Cursor {
+    Cursor {
+        order: "newest.desc".to_owned(),
+        key: Some(SortValue::Time(order.created_at).to_string()),
+        id: order.id,
+    }
+}
+
+/// Where an order list page starts: `created_at` and id of the order before it.
+pub fn order_after(page: &PageRequest) -> Result<Option<(NaiveDateTime, i64)>, AppError> {
+    let Some(cursor) = &page.after else {
+        return Ok(None);
+    };
+
+    let raw = cursor.key_for("newest.desc")?.ok_or_else(invalid_cursor)?;
+    let created_at = NaiveDateTime::parse_from_str(raw, CURSOR_TIME_FORMAT).map_err(|_| invalid_cursor())?;
+
+    Ok(Some((created_at, cursor.id)))
+}
+
+/// Cursor of a list ordered by id alone.
+pub fn id_cursor(id: i64) -> Cursor {
+    Cursor {
+        order: "id".to_owned(),
+        key: None,
+        id,
+    }
+}
+
+/// Where a list ordered by id starts: the id of the row before it.
+pub fn id_after(page: &PageRequest) -> Result<Option<i64>, AppError> {
+    match &page.after {
+        Some(cursor) => match cursor.key_for("id")? {
+            None => Ok(Some(cursor.id)),
+            Some(_) => Err(invalid_cursor()),
+        },
+        None => Ok(None),
+    }
+}
+
+#[async_trait]
+pub trait ItemRepository: Send + Sync {
+    async fn get(&self, id: i64) -> Result<Option<Item>, AppError>;
+
+    /// One page of the items passing `filter`, in `order`.
+    async fn browse(&self, filter: &ItemFilter, order: ItemOrder, page: &PageRequest) -> Result<Page<Item>, AppError>;
+
+    /// Counts per category and, when `filter.currency` is set, per
+    /// [`PRICE_BUCKETS`] bucket. Each facet ignores its own filter, so the
+    /// other choices keep their counts.
+    async fn facets(&self, filter: &ItemFilter) -> Result<ItemFacets, AppError>;
+
+    /// Items with at most `threshold` in stock, lowest first.
+    async fn low_stock(&self, threshold: i32, limit: i64) -> Result<Vec<Item>, AppError>;
+
+    async fn create(&self, item: NewItem) -> Result<Item, AppError>;
+
+    /// Writes only the fields set in `changes`, so a concurrent checkout's
+    /// stock decrement survives an edit that leaves `quantity` alone.
+    /// Returns the item as stored afterwards, `None` if it does not exist.
+    async fn update(&self, id: i64, changes: &UpdateItem) -> Result<Option<Item>, AppError>;
+
+    async fn delete(&self, id: i64) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait CategoryRepository: Send + Sync {
+    /// One page of the categories, by id.
+    async fn list(&self, page: &PageRequest) -> Result<Page<Category>, AppError>;
+
+    /// Every category, by id, for building the tree.
+    async fn all(&self) -> Result<Vec<Category>, AppError>;
+
+    async fn get(&self, id: i64) -> Result<Option<Category>, AppError>;
+
+    /// The first category, by id, with this name at any depth.
+    async fn find_by_name(&self, name: &str) -> Result<Option<Category>, AppError>;
+
+    /// The child of `parent_id` (top level for `None`) with this name,
+    /// compared case-insensitively.
+    async fn find_child(&self, parent_id: Option<i64>, name: &str) -> Result<Option<Category>, AppError>;
+
+    /// The category and its ancestors, top-level first; empty if it does
+    /// not exist.
+    async fn ancestors(&self, id: i64) -> Result<Vec<Category>, AppError>;
+
+    /// Ids of the given categories and all their descendants.
+    async fn descendant_ids(&self, ids: &[i64]) -> Result<Vec<i64>, AppError>;
+
+    async fn create(&self, name: &str, parent_id: Option<i64>) -> Result<Category, AppError>;
+
+    /// Saves the name and parent of the category. A parent inside the
+    /// category's own subtree is refused with a validation error on
+    /// `parent_id`, checked atomically with the write so concurrent moves
+    /// cannot form a cycle.
+    async fn update(&self, category: &Category) -> Result<(), AppError>;
+
+    async fn delete(&self, id: i64) -> Result<(), AppError>;
+
+    /// Whether any item still references the category.
+    async fn has_items(&self, id: i64) -> Result<bool, AppError>;
+
+    /// Whether any category is nested under this one.
+    async fn has_children(&self, id: i64) -> Result<bool, AppError>;
+}
+
+/// A stored refresh token. Only the hash of the token itself is kept.
+#[derive(Debug, Clone, FromRow)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub family_id: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// What `require_auth` compares the claims of a token against.
+#[derive(Debug)]
+pub struct TokenState {
+    pub token_version: i64,
+    pub role: String,
+    /// The token's `jti` is deny-listed.
+    pub revoked: bool,
+}
+
+/// Users, roles and everything needed to issue and revoke their tokens.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn find_role(&self, name: &str) -> Result<Option<Role>, AppError>;
+
+    async fn role_name(&self, role_id: i64) -> Result<String, AppError>;
+
+    /// Fails with `AppError::Conflict` when the username is taken.
+    async fn create(&self, username: &str, password_hash: &str, role_id: i64) -> Result<User, AppError>;
+
+    async fn get(&self, id: i64) -> Result<Option<User>, AppError>;
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError>;
+
+    async fn set_password(&self, id: i64, password_hash: &str) -> Result<(), AppError>;
+
+    /// Returns `false` when the user does not exist.
+    async fn set_role(&self, id: i64, role_id: i64) -> Result<bool, AppError>;
+
+    /// `None` when the user no longer exists.
+    async fn token_state(&self, user_id: i64, jti: &str) -> Result<Option<TokenState>, AppError>;
+
+    async fn store_refresh_token(
+        &self,
+        user_id: i64,
+        family_id: &str,
+        token_hash: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), AppError>;
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError>;
+
+    /// Marks the token as used unless it already is, or was revoked.
+    /// Returns whether this call marked it.
+    async fn use_refresh_token(&self, id: i64) -> Result<bool, AppError>;
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AppError>;
+
+    /// Deny-lists an access token until it expires, and drops entries that
+    /// are past their expiry.
+    async fn deny_access_token(&self, jti: &str, expires_at: NaiveDateTime) -> Result<(), AppError>;
+
+    /// Bumps the user's token version and revokes all its refresh tokens.
+    async fn revoke_user_tokens(&self, user_id: i64) -> Result<(), AppError>;
+}
+
+/// A cart line joined with the item it refers to.
+#[derive(Debug, FromRow)]
+pub struct CartRow {
+    pub item_id: i64,
+    pub name: String,
+    pub quantity: i32,
+    /// Price when the cart was last shown.
+    pub stored_price: Decimal,
+    pub current_price: Decimal,
+    pub currency: Currency,
+    pub available: i32,
+}
+
+#[async_trait]
+pub trait CartRepository: Send + Sync {
+    /// Lines in the order they were added.
+    async fn lines(&self, user_id: i64) -> Result<Vec<CartRow>, AppError>;
+
+    /// Brings the stored prices up to the current item prices.
+    async fn reprice(&self, user_id: i64) -> Result<(), AppError>;
+
+    /// Quantity of the item in the cart, if it is there.
+    async fn quantity(&self, user_id: i64, item_id: i64) -> Result<Option<i32>, AppError>;
+
+    /// Currency of any other line in the cart.
+    async fn currency(&self, user_id: i64, except_item_id: i64) -> Result<Option<Currency>, AppError>;
+
+    /// Inserts the line or replaces its quantity and price.
+    async fn put(&self, user_id: i64, item_id: i64, quantity: i32, unit_price: Decimal) -> Result<(), AppError>;
+
+    async fn set_quantity(&self, user_id: i64, item_id: i64, quantity: i32) -> Result<(), AppError>;
+
+    /// Returns `false` when the item was not in the cart.
+    async fn remove(&self, user_id: i64, item_id: i64) -> Result<bool, AppError>;
+
+    async fn clear(&self, user_id: i64) -> Result<(), AppError>;
+}
+
+/// Builds the order lines and total from the requested quantities and the
+/// locked items (`None` for items that do not exist).
+pub type PlanOrder<'a> =
+    dyn Fn(&[(i64, i32, Option<Item>)]) -> Result<(Vec<OrderLine>, Money), AppError> + Send + Sync + 'a;
+
+/// Order as seen by someone trying to change its status.
+#[derive(Debug)]
+pub struct OrderAccess {
+    pub user_id: i64,
+    pub status: String,
+    /// The order contains items of the caller.
+    pub has_seller_items: bool,
+    /// Every line of the order is an item of the caller.
+    pub only_seller_items: bool,
+}
+
+/// Decides whether a status change may go ahead; gets `None` for an order
+/// that does not exist.
+pub type CheckTransition<'a> = dyn Fn(Option<&OrderAccess>) -> Result<(), AppError> + Send + Sync + 'a;
+
+#[async_trait]
+pub trait OrderRepository: Send + Sync {
+    /// Places an order atomically: locks the items, lets `plan` price the
+    /// lines, takes the quantities out of stock and stores the order. With
+    /// `lines` set to `None` the user's cart is bought and then emptied.
+    async fn checkout(
+        &self,
+        user_id: i64,
+        lines: Option<BTreeMap<i64, i32>>,
+        plan: &PlanOrder<'_>,
+    ) -> Result<OrderDetail, AppError>;
+
+    /// One page of the orders of a buyer, newest first.
+    async fn list_for_buyer(&self, user_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError>;
+
+    /// One page of the orders containing items of a seller, newest first,
+    /// with only that seller's lines.
+    async fn list_for_seller(&self, seller_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError>;
+
+    async fn get(&self, id: i64) -> Result<Option<OrderDetail>, AppError>;
+
+    /// Locks the order, lets `check` accept or reject the change, then moves
+    /// it to `next` and stamps the matching timestamp. Cancelling puts the
+    /// ordered quantities back in stock. `caller_id` is used to fill
+    /// [`OrderAccess::has_seller_items`] and
+    /// [`OrderAccess::only_seller_items`].
+    async fn transition(
+        &self,
+        id: i64,
+        caller_id: i64,
+        next: OrderStatus,
+        check: &CheckTransition<'_>,
+    ) -> Result<OrderDetail, AppError>;
+}
+
+/// Usage of the connection pool.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+/// What the readiness probe asks the store.
+#[async_trait]
+pub trait HealthRepository: Send + Sync {
+    /// One round trip to the database.
+    async fn ping(&self) -> Result<(), AppError>;
+
+    /// Embedded migrations that are not applied yet; `None` when the
+    /// database has no migrations table. Must not change the schema.
+    async fn pending_migrations(&self) -> Result<Option<usize>, AppError>;
+
+    /// `None` for stores without a connection pool.
+    fn pool_stats(&self) -> Option<PoolStats>;
+}
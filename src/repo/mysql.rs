@@ -0,0 +1,1470 @@
+use super::{
+    category_cycle, id_after, id_cursor, order_after, order_cursor, price_facets, CartRepository, CartRow,
+    CategoryRepository, CheckTransition, HealthRepository, ItemFilter, ItemOrder, ItemRepository,
+    NewItem, OrderAccess, OrderRepository, PlanOrder, PoolStats, RefreshToken, SortValue,
+    TokenState, UserRepository, PRICE_BUCKETS, UNSOLD_STATUSES,
+};
+use crate::db;
+use crate::error::AppError;
+use crate::models::{
+    Category, CategoryFacet, Item, ItemFacets, ItemRow, ItemSort, Order, OrderDetail, OrderLine,
+    OrderLineRow, OrderRow, OrderStatus, Role, SortOrder, UpdateItem, User,
+};
+use crate::money::Currency;
+use crate::pagination::{Page, PageRequest};
+use crate::search::{self, SearchMode};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use sqlx::{Connection, FromRow, MySql, MySqlPool, QueryBuilder, Row, Transaction};
+use std::collections::BTreeMap;
+
+/// Production backend.
+#[derive(Clone)]
+pub struct MySqlStore {
+    pool: MySqlPool,
+}
+
+impl MySqlStore {
+    pub fn new(pool: MySqlPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Logs a failed query and converts the error.
+fn db_err(what: &'static str) -> impl FnOnce(sqlx::Error) -> AppError {
+    move |e| {
+        tracing::error!("{} failed: {:?}", what, e);
+        AppError::from(e)
+    }
+}
+
+/// A category and its ancestors, top-level first. `seen` holds the ids
+/// visited so far, so the walk ends even if the parents ever form a loop.
+const CATEGORY_PATH: &str = r#"
+    WITH RECURSIVE path (id, parent_id, name, depth, seen) AS (
+        SELECT id, parent_id, name, 0, CAST(id AS CHAR(4000)) FROM categories WHERE id = ?
+        UNION ALL
+        SELECT c.id, c.parent_id, c.name, path.depth + 1, CONCAT(path.seen, ',', c.id)
+        FROM categories c JOIN path ON c.id = path.parent_id
+        WHERE NOT FIND_IN_SET(c.id, path.seen)
+    )
+    SELECT id, parent_id, name FROM path ORDER BY depth DESC
+"#;
+
+const ORDER_COLUMNS: &str = "id, user_id, status, total, currency, created_at, \
+     paid_at, shipped_at, delivered_at, cancelled_at, refunded_at";
+
+/// Facet whose own filter is left out while counting it.
+#[derive(PartialEq)]
+enum Facet {
+    None,
+    Category,
+    Price,
+}
+
+/// Appends the `WHERE` clause of `filter`. Columns are qualified, since the
+/// category facet joins `categories`.
+fn push_item_filter(query: &mut QueryBuilder<'_, MySql>, filter: &ItemFilter, skip: Facet) {
+    query.push(" WHERE 1 = 1");
+
+    match filter.search.as_deref().map(SearchMode::parse) {
+        Some(SearchMode::Words(words)) => {
+            query
+                .push(" AND MATCH (items.name, items.description) AGAINST (")
+                .push_bind(search::boolean_query(&words))
+                .push(" IN BOOLEAN MODE)");
+        }
+        Some(SearchMode::Substring(text)) if !text.is_empty() => {
+            query
+                .push(" AND (items.name LIKE ")
+                .push_bind(search::like_pattern(&text))
+                .push(" OR items.description LIKE ")
+                .push_bind(search::like_pattern(&text))
+                .push(")");
+        }
+        _ => {}
+    }
+
+    if skip != Facet::Price {
+        if let Some(min) = filter.min_price {
+            query.push(" AND items.price >= ").push_bind(min);
+        }
+        if let Some(max) = filter.max_price {
+            query.push(" AND items.price <= ").push_bind(max);
+        }
+    }
+
+    if let Some(currency) = filter.currency {
+        query.push(" AND items.currency = ").push_bind(currency);
+    }
+
+    if filter.in_stock {
+        query.push(" AND items.quantity > 0");
+    }
+
+    if let Some(owner_id) = filter.owner_id {
+        query.push(" AND items.owner_id = ").push_bind(owner_id);
+    }
+
+    if skip != Facet::Category && !filter.category_ids.is_empty() {
+        query.push(" AND items.category_id IN (");
+        let mut ids = query.separated(", ");
+        for id in &filter.category_ids {
+            ids.push_bind(*id);
+        }
+        query.push(")");
+    }
+}
+
+fn push_unsold_statuses(query: &mut QueryBuilder<'_, MySql>) {
+    let mut statuses = query.separated(", ");
+    for status in UNSOLD_STATUSES {
+        statuses.push_bind(status.as_str());
+    }
+}
+
+fn sql_direction(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => " ASC",
+        SortOrder::Desc => " DESC",
+    }
+}
+
+fn push_sort_value(query: &mut QueryBuilder<'_, MySql>, value: &SortValue) {
+    match value {
+        SortValue::Decimal(d) => query.push_bind(*d),
+        SortValue::Text(s) => query.push_bind(s.clone()),
+        SortValue::Time(t) => query.push_bind(*t),
+        SortValue::Count(n) => query.push_bind(*n),
+        SortValue::Score(x) => query.push_bind(*x),
+    };
+}
+
+#[async_trait]
+impl ItemRepository for MySqlStore {
+    #[tracing::instrument(name = "db items.browse", skip_all, fields(db.system = "mysql"))]
+    async fn browse(&self, filter: &ItemFilter, order: ItemOrder, page: &PageRequest) -> Result<Page<Item>, AppError> {
+        let after = order.after(page)?;
+
+        // Keys and directions come from enums, never from the request text.
+        // The key is computed in a derived table, so the cursor condition
+        // can compare against it.
+        let mut query = QueryBuilder::new(
+            "SELECT * FROM (SELECT items.id, items.name, items.description, items.price, items.currency, \
+             items.quantity, items.category_id, items.owner_id, items.created_at, ",
+        );
+        match order {
+            ItemOrder::Id => {
+                query.push("NULL");
+            }
+            ItemOrder::Relevance => {
+                let words = match filter.search.as_deref().map(SearchMode::parse) {
+                    Some(SearchMode::Words(words)) => words,
+                    _ => Vec::new(),
+                };
+                query
+                    .push("MATCH (items.name, items.description) AGAINST (")
+                    .push_bind(search::boolean_query(&words))
+                    .push(" IN BOOLEAN MODE)");
+            }
+            ItemOrder::Sorted(ItemSort::Price, _) => {
+                query.push("items.price");
+            }
+            ItemOrder::Sorted(ItemSort::Name, _) => {
+                query.push("items.name");
+            }
+            ItemOrder::Sorted(ItemSort::Newest, _) => {
+                query.push("items.created_at");
+            }
+            ItemOrder::Sorted(ItemSort::Popularity, _) => {
+                query.push(
+                    "(SELECT CAST(COALESCE(SUM(oi.quantity), 0) AS SIGNED) \
+                     FROM order_items oi JOIN orders o ON o.id = oi.order_id \
+                     WHERE oi.item_id = items.id AND o.status NOT IN (",
+                );
+                push_unsold_statuses(&mut query);
+                query.push("))");
+            }
+        }
+        query.push(" AS sort_key FROM items");
+        push_item_filter(&mut query, filter, Facet::None);
+        query.push(") AS listing");
+
+        let (key_direction, id_direction) = match order {
+            ItemOrder::Id => (None, SortOrder::Asc),
+            ItemOrder::Relevance => (Some(SortOrder::Desc), SortOrder::Asc),
+            ItemOrder::Sorted(_, direction) => (Some(direction), direction),
+        };
+        let (key_op, id_op) = (
+            if key_direction == Some(SortOrder::Desc) { " < " } else { " > " },
+            if id_direction == SortOrder::Desc { " < " } else { " > " },
+        );
+
+        match after {
+            Some((Some(key), id)) => {
+                query.push(" WHERE sort_key").push(key_op);
+                push_sort_value(&mut query, &key);
+                query.push(" OR (sort_key = ");
+                push_sort_value(&mut query, &key);
+                query.push(" AND id").push(id_op).push_bind(id).push(")");
+            }
+            Some((None, id)) => {
+                query.push(" WHERE id").push(id_op).push_bind(id);
+            }
+            None => {}
+        }
+
+        query.push(" ORDER BY ");
+        if let Some(direction) = key_direction {
+            query.push("sort_key").push(sql_direction(direction)).push(", ");
+        }
+        query
+            .push("id")
+            .push(sql_direction(id_direction))
+            .push(" LIMIT ")
+            .push_bind(page.fetch())
+            .push(" OFFSET ")
+            .push_bind(page.offset);
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Listing items"))?;
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let item = Item::from(ItemRow::from_row(row)?);
+                let key = match order {
+                    ItemOrder::Id => None,
+                    ItemOrder::Relevance => Some(SortValue::Score(row.try_get("sort_key")?)),
+                    ItemOrder::Sorted(ItemSort::Price, _) => Some(SortValue::Decimal(item.price.amount)),
+                    ItemOrder::Sorted(ItemSort::Name, _) => Some(SortValue::Text(item.name.clone())),
+                    ItemOrder::Sorted(ItemSort::Newest, _) => Some(SortValue::Time(item.created_at)),
+                    ItemOrder::Sorted(ItemSort::Popularity, _) => Some(SortValue::Count(row.try_get("sort_key")?)),
+                };
+                let cursor = order.cursor(key.as_ref(), item.id);
+                Ok((item, cursor))
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()
+            .map_err(db_err("Reading items"))?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM items");
+        push_item_filter(&mut count, filter, Facet::None);
+        let (total,): (i64,) = count
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Counting items"))?;
+
+        Ok(Page::new(rows, total, page))
+    }
+
+    #[tracing::instrument(name = "db items.facets", skip_all, fields(db.system = "mysql"))]
+    async fn facets(&self, filter: &ItemFilter) -> Result<ItemFacets, AppError> {
+        let mut query = QueryBuilder::new(
+            "SELECT items.category_id, categories.name, COUNT(*) AS count \
+             FROM items LEFT JOIN categories ON categories.id = items.category_id",
+        );
+        push_item_filter(&mut query, filter, Facet::Category);
+        query.push(" GROUP BY items.category_id, categories.name ORDER BY count DESC, items.category_id");
+
+        let categories = query
+            .build_query_as::<(Option<i64>, Option<String>, i64)>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Counting items per category"))?
+            .into_iter()
+            .map(|(category_id, name, count)| CategoryFacet { category_id, name, count })
+            .collect();
+
+        if filter.currency.is_none() {
+            return Ok(ItemFacets {
+                categories,
+                price: Vec::new(),
+            });
+        }
+
+        let mut query = QueryBuilder::new("SELECT CASE");
+        for (bucket, min) in PRICE_BUCKETS.iter().enumerate().skip(1) {
+            query
+                .push(" WHEN items.price < ")
+                .push_bind(Decimal::from(*min))
+                .push(" THEN ")
+                .push_bind((bucket - 1) as i64);
+        }
+        query
+            .push(" ELSE ")
+            .push_bind((PRICE_BUCKETS.len() - 1) as i64)
+            .push(" END AS bucket, COUNT(*) AS count FROM items");
+        push_item_filter(&mut query, filter, Facet::Price);
+        query.push(" GROUP BY bucket");
+
+        let counts: BTreeMap<i64, i64> = query
+            .build_query_as::<(i64, i64)>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Counting items per price range"))?
+            .into_iter()
+            .collect();
+
+        Ok(ItemFacets {
+            categories,
+            price: price_facets(|bucket| counts.get(&(bucket as i64)).copied().unwrap_or(0)),
+        })
+    }
+
+    #[tracing::instrument(name = "db items.get", skip_all, fields(db.system = "mysql"))]
+    async fn get(&self, id: i64) -> Result<Option<Item>, AppError> {
+        let row = sqlx::query_as::<_, ItemRow>(
+            r#"
+            SELECT id, name, description, price, currency, quantity, category_id, owner_id, created_at
+            FROM items
+            WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading item"))?;
+
+        Ok(row.map(Item::from))
+    }
+
+    #[tracing::instrument(name = "db items.low_stock", skip_all, fields(db.system = "mysql"))]
+    async fn low_stock(&self, threshold: i32, limit: i64) -> Result<Vec<Item>, AppError> {
+        let rows = sqlx::query_as::<_, ItemRow>(
+            r#"
+            SELECT id, name, description, price, currency, quantity, category_id, owner_id, created_at
+            FROM items
+            WHERE quantity <= ?
+            ORDER BY quantity, id
+            LIMIT ?
+            "#,
+        )
+        .bind(threshold)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_err("Loading low-stock items"))?;
+
+        Ok(rows.into_iter().map(Item::from).collect())
+    }
+
+    #[tracing::instrument(name = "db items.create", skip_all, fields(db.system = "mysql"))]
+    async fn create(&self, item: NewItem) -> Result<Item, AppError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO items (name, description, price, currency, quantity, category_id, owner_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&item.name)
+        .bind(&item.description)
+        .bind(item.price.amount)
+        .bind(item.price.currency)
+        .bind(item.quantity)
+        .bind(item.category_id)
+        .bind(item.owner_id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Inserting item"))?;
+
+        let id = result.last_insert_id() as i64;
+
+        // Read back for the timestamp MySQL filled in.
+        ItemRepository::get(self, id)
+            .await?
+            .ok_or_else(|| AppError::Internal(format!("Item {} vanished after insert.", id)))
+    }
+
+    #[tracing::instrument(name = "db items.update", skip_all, fields(db.system = "mysql"))]
+    async fn update(&self, id: i64, changes: &UpdateItem) -> Result<Option<Item>, AppError> {
+        let mut tx = self.pool.begin().await.map_err(db_err("Starting item transaction"))?;
+
+        // `id = id` keeps the statement valid when nothing is set.
+        let mut query = QueryBuilder::<MySql>::new("UPDATE items SET ");
+        let mut set = query.separated(", ");
+        set.push("id = id");
+        if let Some(name) = &changes.name {
+            set.push("name = ").push_bind_unseparated(name);
+        }
+        if let Some(description) = &changes.description {
+            set.push("description = ").push_bind_unseparated(description);
+        }
+        if let Some(price) = &changes.price {
+            set.push("price = ").push_bind_unseparated(price.amount);
+            set.push("currency = ").push_bind_unseparated(price.currency);
+        }
+        if let Some(quantity) = changes.quantity {
+            set.push("quantity = ").push_bind_unseparated(quantity);
+        }
+        if let Some(category_id) = changes.category_id {
+            set.push("category_id = ").push_bind_unseparated(category_id);
+        }
+        query.push(" WHERE id = ").push_bind(id);
+
+        query
+            .build()
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err("Updating item"))?;
+
+        // Read back under the row lock the update holds.
+        let row = sqlx::query_as::<_, ItemRow>(
+            r#"
+            SELECT id, name, description, price, currency, quantity, category_id, owner_id, created_at
+            FROM items
+            WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_err("Loading item"))?;
+
+        tx.commit().await.map_err(db_err("Committing item update"))?;
+
+        Ok(row.map(Item::from))
+    }
+
+    #[tracing::instrument(name = "db items.delete", skip_all, fields(db.system = "mysql"))]
+    async fn delete(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query(r#"DELETE FROM items WHERE id = ?"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Deleting item"))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CategoryRepository for MySqlStore {
+    #[tracing::instrument(name = "db categories.list", skip_all, fields(db.system = "mysql"))]
+    async fn list(&self, page: &PageRequest) -> Result<Page<Category>, AppError> {
+        let after = id_after(page)?;
+
+        let rows = sqlx::query_as::<_, Category>(
+            r#"SELECT id, parent_id, name FROM categories WHERE id > ? ORDER BY id LIMIT ?"#,
+        )
+        .bind(after.unwrap_or(0))
+        .bind(page.fetch())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_err("Loading categories"))?;
+
+        let (total,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM categories"#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Counting categories"))?;
+
+        let rows = rows.into_iter().map(|c| {
+            let cursor = id_cursor(c.id);
+            (c, cursor)
+        });
+
+        Ok(Page::new(rows.collect(), total, page))
+    }
+
+    #[tracing::instrument(name = "db categories.all", skip_all, fields(db.system = "mysql"))]
+    async fn all(&self) -> Result<Vec<Category>, AppError> {
+        sqlx::query_as::<_, Category>(r#"SELECT id, parent_id, name FROM categories ORDER BY id"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading category tree"))
+    }
+
+    #[tracing::instrument(name = "db categories.get", skip_all, fields(db.system = "mysql"))]
+    async fn get(&self, id: i64) -> Result<Option<Category>, AppError> {
+        sqlx::query_as::<_, Category>(r#"SELECT id, parent_id, name FROM categories WHERE id = ?"#)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_err("Loading category"))
+    }
+
+    #[tracing::instrument(name = "db categories.find_by_name", skip_all, fields(db.system = "mysql"))]
+    async fn find_by_name(&self, name: &str) -> Result<Option<Category>, AppError> {
+        sqlx::query_as::<_, Category>(
+            r#"SELECT id, parent_id, name FROM categories WHERE name = ? ORDER BY id LIMIT 1"#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading category by name"))
+    }
+
+    #[tracing::instrument(name = "db categories.find_child", skip_all, fields(db.system = "mysql"))]
+    async fn find_child(&self, parent_id: Option<i64>, name: &str) -> Result<Option<Category>, AppError> {
+        // The column collation compares case-insensitively, like the
+        // unique index on (sibling_key, name).
+        sqlx::query_as::<_, Category>(
+            r#"SELECT id, parent_id, name FROM categories WHERE sibling_key = ? AND name = ?"#,
+        )
+        .bind(parent_id.unwrap_or(0))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading category by parent and name"))
+    }
+
+    #[tracing::instrument(name = "db categories.ancestors", skip_all, fields(db.system = "mysql"))]
+    async fn ancestors(&self, id: i64) -> Result<Vec<Category>, AppError> {
+        sqlx::query_as::<_, Category>(CATEGORY_PATH)
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading category path"))
+    }
+
+    #[tracing::instrument(name = "db categories.descendant_ids", skip_all, fields(db.system = "mysql"))]
+    async fn descendant_ids(&self, ids: &[i64]) -> Result<Vec<i64>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // UNION rather than UNION ALL, so overlapping subtrees are walked
+        // once.
+        let mut query = QueryBuilder::<MySql>::new(
+            "WITH RECURSIVE subtree (id) AS (SELECT id FROM categories WHERE id IN (",
+        );
+        let mut roots = query.separated(", ");
+        for id in ids {
+            roots.push_bind(*id);
+        }
+        query.push(
+            ") UNION SELECT c.id FROM categories c JOIN subtree ON c.parent_id = subtree.id) \
+             SELECT id FROM subtree ORDER BY id",
+        );
+
+        query
+            .build_query_scalar::<i64>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading subcategories"))
+    }
+
+    #[tracing::instrument(name = "db categories.create", skip_all, fields(db.system = "mysql"))]
+    async fn create(&self, name: &str, parent_id: Option<i64>) -> Result<Category, AppError> {
+        let result = sqlx::query(r#"INSERT INTO categories (parent_id, name) VALUES (?, ?)"#)
+            .bind(parent_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Inserting category"))?;
+
+        Ok(Category {
+            id: result.last_insert_id() as i64,
+            parent_id,
+            name: name.to_string(),
+        })
+    }
+
+    #[tracing::instrument(name = "db categories.update", skip_all, fields(db.system = "mysql"))]
+    async fn update(&self, category: &Category) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await.map_err(db_err("Starting category transaction"))?;
+
+        if let Some(parent_id) = category.parent_id {
+            // Moves are rare, so every category is locked: concurrent moves
+            // then run one after the other, and the path read below cannot
+            // change before the commit.
+            sqlx::query(r#"SELECT id FROM categories FOR UPDATE"#)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_err("Locking categories"))?;
+
+            let path = sqlx::query_as::<_, Category>(CATEGORY_PATH)
+                .bind(parent_id)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(db_err("Loading category path"))?;
+
+            if path.iter().any(|c| c.id == category.id) {
+                tracing::warn!("Moving category {} under {} would form a cycle", category.id, parent_id);
+                return Err(category_cycle());
+            }
+        }
+
+        sqlx::query(r#"UPDATE categories SET parent_id = ?, name = ? WHERE id = ?"#)
+            .bind(category.parent_id)
+            .bind(&category.name)
+            .bind(category.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err("Updating category"))?;
+
+        tx.commit().await.map_err(db_err("Committing category"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db categories.delete", skip_all, fields(db.system = "mysql"))]
+    async fn delete(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query(r#"DELETE FROM categories WHERE id = ?"#)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Deleting category"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db categories.has_items", skip_all, fields(db.system = "mysql"))]
+    async fn has_items(&self, id: i64) -> Result<bool, AppError> {
+        let exists: i64 =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM items WHERE category_id = ?)"#)
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(db_err("Checking category items"))?;
+
+        Ok(exists != 0)
+    }
+
+    #[tracing::instrument(name = "db categories.has_children", skip_all, fields(db.system = "mysql"))]
+    async fn has_children(&self, id: i64) -> Result<bool, AppError> {
+        let exists: i64 =
+            sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM categories WHERE parent_id = ?)"#)
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(db_err("Checking subcategories"))?;
+
+        Ok(exists != 0)
+    }
+}
+
+#[derive(FromRow)]
+struct TokenStateRow {
+    token_version: i64,
+    role: String,
+    revoked: i64,
+}
+
+#[async_trait]
+impl UserRepository for MySqlStore {
+    #[tracing::instrument(name = "db users.find_role", skip_all, fields(db.system = "mysql"))]
+    async fn find_role(&self, name: &str) -> Result<Option<Role>, AppError> {
+        sqlx::query_as::<_, Role>(r#"SELECT id, name FROM roles WHERE name = ?"#)
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_err("Loading role"))
+    }
+
+    #[tracing::instrument(name = "db users.role_name", skip_all, fields(db.system = "mysql"))]
+    async fn role_name(&self, role_id: i64) -> Result<String, AppError> {
+        sqlx::query_scalar(r#"SELECT name FROM roles WHERE id = ?"#)
+            .bind(role_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Loading role name"))
+    }
+
+    #[tracing::instrument(name = "db users.create", skip_all, fields(db.system = "mysql"))]
+    async fn create(&self, username: &str, password_hash: &str, role_id: i64) -> Result<User, AppError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO users (username, password_hash, role_id)
+            VALUES (?, ?, ?)
+            "#,
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(role_id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Inserting user"))?;
+
+        Ok(User {
+            id: result.last_insert_id() as i64,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            role_id,
+            token_version: 0,
+        })
+    }
+
+    #[tracing::instrument(name = "db users.get", skip_all, fields(db.system = "mysql"))]
+    async fn get(&self, id: i64) -> Result<Option<User>, AppError> {
+        sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash, role_id, token_version
+            FROM users
+            WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading user"))
+    }
+
+    #[tracing::instrument(name = "db users.find_by_username", skip_all, fields(db.system = "mysql"))]
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, username, password_hash, role_id, token_version
+            FROM users
+            WHERE username = ?
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading user by name"))
+    }
+
+    #[tracing::instrument(name = "db users.set_password", skip_all, fields(db.system = "mysql"))]
+    async fn set_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        sqlx::query(r#"UPDATE users SET password_hash = ? WHERE id = ?"#)
+            .bind(password_hash)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Updating password"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db users.set_role", skip_all, fields(db.system = "mysql"))]
+    async fn set_role(&self, id: i64, role_id: i64) -> Result<bool, AppError> {
+        let result = sqlx::query(r#"UPDATE users SET role_id = ? WHERE id = ?"#)
+            .bind(role_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Updating role"))?;
+
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        // MySQL reports 0 affected rows when the role did not change.
+        let exists: i64 = sqlx::query_scalar(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = ?)"#)
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Checking user"))?;
+
+        Ok(exists != 0)
+    }
+
+    #[tracing::instrument(name = "db users.token_state", skip_all, fields(db.system = "mysql"))]
+    async fn token_state(&self, user_id: i64, jti: &str) -> Result<Option<TokenState>, AppError> {
+        let row = sqlx::query_as::<_, TokenStateRow>(
+            r#"
+            SELECT u.token_version, r.name AS role,
+                   EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = ?) AS revoked
+            FROM users u
+            JOIN roles r ON r.id = u.role_id
+            WHERE u.id = ?
+            "#,
+        )
+        .bind(jti)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Token check"))?;
+
+        Ok(row.map(|r| TokenState {
+            token_version: r.token_version,
+            role: r.role,
+            revoked: r.revoked != 0,
+        }))
+    }
+
+    #[tracing::instrument(name = "db users.store_refresh_token", skip_all, fields(db.system = "mysql"))]
+    async fn store_refresh_token(
+        &self,
+        user_id: i64,
+        family_id: &str,
+        token_hash: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(user_id)
+        .bind(family_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Storing refresh token"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db users.find_refresh_token", skip_all, fields(db.system = "mysql"))]
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        sqlx::query_as::<_, RefreshToken>(
+            r#"
+            SELECT id, user_id, family_id, expires_at, used_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = ?
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Refresh token lookup"))
+    }
+
+    #[tracing::instrument(name = "db users.use_refresh_token", skip_all, fields(db.system = "mysql"))]
+    async fn use_refresh_token(&self, id: i64) -> Result<bool, AppError> {
+        // Guarded on `used_at IS NULL` so two concurrent refreshes with the
+        // same token cannot both succeed.
+        let result = sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET used_at = UTC_TIMESTAMP()
+            WHERE id = ? AND used_at IS NULL AND revoked_at IS NULL
+            "#,
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Marking refresh token as used"))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(name = "db users.revoke_family", skip_all, fields(db.system = "mysql"))]
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = UTC_TIMESTAMP()
+            WHERE family_id = ? AND revoked_at IS NULL
+            "#,
+        )
+        .bind(family_id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Revoking token family"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db users.deny_access_token", skip_all, fields(db.system = "mysql"))]
+    async fn deny_access_token(&self, jti: &str, expires_at: NaiveDateTime) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT IGNORE INTO revoked_tokens (jti, expires_at)
+            VALUES (?, ?)
+            "#,
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Revoking access token"))?;
+
+        // Entries past their expiry are useless, the token would be rejected anyway.
+        sqlx::query(r#"DELETE FROM revoked_tokens WHERE expires_at < UTC_TIMESTAMP()"#)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Pruning revoked tokens"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db users.revoke_user_tokens", skip_all, fields(db.system = "mysql"))]
+    async fn revoke_user_tokens(&self, user_id: i64) -> Result<(), AppError> {
+        sqlx::query(r#"UPDATE users SET token_version = token_version + 1 WHERE id = ?"#)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Bumping token version"))?;
+
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = UTC_TIMESTAMP()
+            WHERE user_id = ? AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Revoking refresh tokens"))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CartRepository for MySqlStore {
+    #[tracing::instrument(name = "db carts.lines", skip_all, fields(db.system = "mysql"))]
+    async fn lines(&self, user_id: i64) -> Result<Vec<CartRow>, AppError> {
+        sqlx::query_as::<_, CartRow>(
+            r#"
+            SELECT c.item_id, i.name, c.quantity, c.unit_price AS stored_price,
+                   i.price AS current_price, i.currency, i.quantity AS available
+            FROM cart_items c
+            JOIN items i ON i.id = c.item_id
+            WHERE c.user_id = ?
+            ORDER BY c.added_at, c.item_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_err("Loading cart"))
+    }
+
+    #[tracing::instrument(name = "db carts.reprice", skip_all, fields(db.system = "mysql"))]
+    async fn reprice(&self, user_id: i64) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE cart_items c
+            JOIN items i ON i.id = c.item_id
+            SET c.unit_price = i.price
+            WHERE c.user_id = ? AND c.unit_price <> i.price
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Repricing cart"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db carts.quantity", skip_all, fields(db.system = "mysql"))]
+    async fn quantity(&self, user_id: i64, item_id: i64) -> Result<Option<i32>, AppError> {
+        sqlx::query_scalar(r#"SELECT quantity FROM cart_items WHERE user_id = ? AND item_id = ?"#)
+            .bind(user_id)
+            .bind(item_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_err("Loading cart line"))
+    }
+
+    #[tracing::instrument(name = "db carts.currency", skip_all, fields(db.system = "mysql"))]
+    async fn currency(&self, user_id: i64, except_item_id: i64) -> Result<Option<Currency>, AppError> {
+        sqlx::query_scalar(
+            r#"
+            SELECT i.currency
+            FROM cart_items c
+            JOIN items i ON i.id = c.item_id
+            WHERE c.user_id = ? AND c.item_id <> ?
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(except_item_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading cart currency"))
+    }
+
+    #[tracing::instrument(name = "db carts.put", skip_all, fields(db.system = "mysql"))]
+    async fn put(&self, user_id: i64, item_id: i64, quantity: i32, unit_price: Decimal) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO cart_items (user_id, item_id, quantity, unit_price)
+            VALUES (?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE quantity = VALUES(quantity), unit_price = VALUES(unit_price)
+            "#,
+        )
+        .bind(user_id)
+        .bind(item_id)
+        .bind(quantity)
+        .bind(unit_price)
+        .execute(&self.pool)
+        .await
+        .map_err(db_err("Adding cart item"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db carts.set_quantity", skip_all, fields(db.system = "mysql"))]
+    async fn set_quantity(&self, user_id: i64, item_id: i64, quantity: i32) -> Result<(), AppError> {
+        sqlx::query(r#"UPDATE cart_items SET quantity = ? WHERE user_id = ? AND item_id = ?"#)
+            .bind(quantity)
+            .bind(user_id)
+            .bind(item_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Updating cart item"))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "db carts.remove", skip_all, fields(db.system = "mysql"))]
+    async fn remove(&self, user_id: i64, item_id: i64) -> Result<bool, AppError> {
+        let result = sqlx::query(r#"DELETE FROM cart_items WHERE user_id = ? AND item_id = ?"#)
+            .bind(user_id)
+            .bind(item_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Removing cart item"))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(name = "db carts.clear", skip_all, fields(db.system = "mysql"))]
+    async fn clear(&self, user_id: i64) -> Result<(), AppError> {
+        sqlx::query(r#"DELETE FROM cart_items WHERE user_id = ?"#)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_err("Clearing cart"))?;
+
+        Ok(())
+    }
+}
+
+#[derive(FromRow)]
+struct GroupedLineRow {
+    order_id: i64,
+    #[sqlx(flatten)]
+    line: OrderLineRow,
+}
+
+async fn load_order(
+    tx: &mut Transaction<'_, MySql>,
+    id: i64,
+) -> Result<OrderDetail, AppError> {
+    let order = sqlx::query_as::<_, OrderRow>(&format!(
+        "SELECT {} FROM orders WHERE id = ?",
+        ORDER_COLUMNS
+    ))
+    .bind(id)
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(db_err("Loading order"))?;
+
+    let lines = sqlx::query_as::<_, OrderLineRow>(
+        r#"
+        SELECT item_id, seller_id, name, unit_price, currency, quantity
+        FROM order_items
+        WHERE order_id = ?
+        ORDER BY id
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut **tx)
+    .await
+    .map_err(db_err("Loading order lines"))?;
+
+    Ok(OrderDetail {
+        order: order.into(),
+        lines: lines.into_iter().map(OrderLine::from).collect(),
+    })
+}
+
+/// Attaches the lines to their orders, keeping the order of `orders`.
+fn group_lines(orders: Vec<OrderRow>, lines: Vec<GroupedLineRow>) -> Vec<OrderDetail> {
+    let mut by_order: BTreeMap<i64, Vec<OrderLine>> = BTreeMap::new();
+    for l in lines {
+        by_order.entry(l.order_id).or_default().push(l.line.into());
+    }
+
+    orders
+        .into_iter()
+        .map(|row| {
+            let order = Order::from(row);
+            let lines = by_order.remove(&order.id).unwrap_or_default();
+            OrderDetail { order, lines }
+        })
+        .collect()
+}
+
+#[derive(FromRow)]
+struct OrderAccessRow {
+    user_id: i64,
+    status: String,
+    has_seller_items: i64,
+    only_seller_items: i64,
+}
+
+/// Appends the cursor condition, newest-first order and limit of an order
+/// list; `prefix` qualifies the columns.
+fn push_order_page(query: &mut QueryBuilder<'_, MySql>, prefix: &str, page: &PageRequest) -> Result<(), AppError> {
+    if let Some((created_at, id)) = order_after(page)? {
+        query
+            .push(format!(" AND ({0}created_at < ", prefix))
+            .push_bind(created_at)
+            .push(format!(" OR ({0}created_at = ", prefix))
+            .push_bind(created_at)
+            .push(format!(" AND {0}id < ", prefix))
+            .push_bind(id)
+            .push("))");
+    }
+
+    query
+        .push(format!(" ORDER BY {0}created_at DESC, {0}id DESC LIMIT ", prefix))
+        .push_bind(page.fetch());
+
+    Ok(())
+}
+
+fn order_page(details: Vec<OrderDetail>, total: i64, page: &PageRequest) -> Page<OrderDetail> {
+    let rows = details
+        .into_iter()
+        .map(|detail| {
+            let cursor = order_cursor(&detail.order);
+            (detail, cursor)
+        })
+        .collect();
+
+    Page::new(rows, total, page)
+}
+
+impl MySqlStore {
+    /// Lines of `orders`, only those of `seller_id` if set.
+    async fn order_lines(&self, orders: &[OrderRow], seller_id: Option<i64>) -> Result<Vec<GroupedLineRow>, AppError> {
+        if orders.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query = QueryBuilder::new(
+            "SELECT order_id, item_id, seller_id, name, unit_price, currency, quantity \
+             FROM order_items WHERE order_id IN (",
+        );
+        let mut ids = query.separated(", ");
+        for order in orders {
+            ids.push_bind(order.id);
+        }
+        query.push(")");
+        if let Some(seller_id) = seller_id {
+            query.push(" AND seller_id = ").push_bind(seller_id);
+        }
+        query.push(" ORDER BY id");
+
+        query
+            .build_query_as::<GroupedLineRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading order lines"))
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MySqlStore {
+    #[tracing::instrument(name = "db orders.checkout", skip_all, fields(db.system = "mysql"))]
+    async fn checkout(
+        &self,
+        user_id: i64,
+        lines: Option<BTreeMap<i64, i32>>,
+        plan: &PlanOrder<'_>,
+    ) -> Result<OrderDetail, AppError> {
+        let mut tx = self.pool.begin().await.map_err(db_err("Starting checkout transaction"))?;
+
+        let from_cart = lines.is_none();
+        let requested = match lines {
+            Some(lines) => lines,
+            None => sqlx::query_as::<_, (i64, i32)>(
+                r#"SELECT item_id, quantity FROM cart_items WHERE user_id = ?"#,
+            )
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(db_err("Loading cart"))?
+            .into_iter()
+            .collect(),
+        };
+
+        // Rows are locked in id order so two checkouts touching the same
+        // items cannot deadlock.
+        let mut locked = Vec::with_capacity(requested.len());
+        for (item_id, quantity) in requested {
+            let item = sqlx::query_as::<_, ItemRow>(
+                r#"
+                SELECT id, name, description, price, currency, quantity, category_id, owner_id, created_at
+                FROM items
+                WHERE id = ?
+                FOR UPDATE
+                "#,
+            )
+            .bind(item_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_err("Locking item"))?;
+
+            locked.push((item_id, quantity, item.map(Item::from)));
+        }
+
+        let (lines, total) = plan(&locked)?;
+
+        for line in &lines {
+            sqlx::query(r#"UPDATE items SET quantity = quantity - ? WHERE id = ?"#)
+                .bind(line.quantity)
+                .bind(line.item_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_err("Decrementing stock"))?;
+        }
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO orders (user_id, status, total, currency)
+            VALUES (?, 'pending', ?, ?)
+            "#,
+        )
+        .bind(user_id)
+        .bind(total.amount)
+        .bind(total.currency)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_err("Inserting order"))?;
+
+        let order_id = result.last_insert_id() as i64;
+
+        for line in &lines {
+            sqlx::query(
+                r#"
+                INSERT INTO order_items
+                    (order_id, item_id, seller_id, name, unit_price, currency, quantity)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(order_id)
+            .bind(line.item_id)
+            .bind(line.seller_id)
+            .bind(&line.name)
+            .bind(line.unit_price.amount)
+            .bind(line.unit_price.currency)
+            .bind(line.quantity)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err("Inserting order line"))?;
+        }
+
+        if from_cart {
+            sqlx::query(r#"DELETE FROM cart_items WHERE user_id = ?"#)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_err("Clearing cart"))?;
+        }
+
+        let detail = load_order(&mut tx, order_id).await?;
+
+        tx.commit().await.map_err(db_err("Committing checkout"))?;
+
+        Ok(detail)
+    }
+
+    #[tracing::instrument(name = "db orders.list_for_buyer", skip_all, fields(db.system = "mysql"))]
+    async fn list_for_buyer(&self, user_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError> {
+        let mut query = QueryBuilder::new(format!("SELECT {} FROM orders WHERE user_id = ", ORDER_COLUMNS));
+        query.push_bind(user_id);
+        push_order_page(&mut query, "", page)?;
+
+        let orders = query
+            .build_query_as::<OrderRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading orders"))?;
+
+        let (total,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM orders WHERE user_id = ?"#)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Counting orders"))?;
+
+        let lines = self.order_lines(&orders, None).await?;
+
+        Ok(order_page(group_lines(orders, lines), total, page))
+    }
+
+    #[tracing::instrument(name = "db orders.list_for_seller", skip_all, fields(db.system = "mysql"))]
+    async fn list_for_seller(&self, seller_id: i64, page: &PageRequest) -> Result<Page<OrderDetail>, AppError> {
+        const SELLS: &str = "EXISTS (SELECT 1 FROM order_items oi WHERE oi.order_id = o.id AND oi.seller_id = ";
+
+        let mut query = QueryBuilder::new(
+            "SELECT o.id, o.user_id, o.status, o.total, o.currency, o.created_at, \
+             o.paid_at, o.shipped_at, o.delivered_at, o.cancelled_at, o.refunded_at \
+             FROM orders o WHERE ",
+        );
+        query.push(SELLS).push_bind(seller_id).push(")");
+        push_order_page(&mut query, "o.", page)?;
+
+        let orders = query
+            .build_query_as::<OrderRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_err("Loading seller orders"))?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM orders o WHERE ");
+        count.push(SELLS).push_bind(seller_id).push(")");
+        let (total,): (i64,) = count
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_err("Counting seller orders"))?;
+
+        let lines = self.order_lines(&orders, Some(seller_id)).await?;
+
+        Ok(order_page(group_lines(orders, lines), total, page))
+    }
+
+    #[tracing::instrument(name = "db orders.get", skip_all, fields(db.system = "mysql"))]
+    async fn get(&self, id: i64) -> Result<Option<OrderDetail>, AppError> {
+        let order = sqlx::query_as::<_, OrderRow>(&format!(
+            "SELECT {} FROM orders WHERE id = ?",
+            ORDER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_err("Loading order"))?;
+
+        let Some(order) = order else {
+            return Ok(None);
+        };
+
+        let lines = sqlx::query_as::<_, OrderLineRow>(
+            r#"
+            SELECT item_id, seller_id, name, unit_price, currency, quantity
+            FROM order_items
+            WHERE order_id = ?
+            ORDER BY id
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_err("Loading order lines"))?;
+
+        Ok(Some(OrderDetail {
+            order: order.into(),
+            lines: lines.into_iter().map(OrderLine::from).collect(),
+        }))
+    }
+
+    #[tracing::instrument(name = "db orders.transition", skip_all, fields(db.system = "mysql"))]
+    async fn transition(
+        &self,
+        id: i64,
+        caller_id: i64,
+        next: OrderStatus,
+        check: &CheckTransition<'_>,
+    ) -> Result<OrderDetail, AppError> {
+        let mut tx = self.pool.begin().await.map_err(db_err("Starting order transaction"))?;
+
+        let access = sqlx::query_as::<_, OrderAccessRow>(
+            r#"
+            SELECT user_id, status,
+                   EXISTS(
+                       SELECT 1 FROM order_items
+                       WHERE order_id = orders.id AND seller_id = ?
+                   ) AS has_seller_items,
+                   NOT EXISTS(
+                       SELECT 1 FROM order_items
+                       WHERE order_id = orders.id AND (seller_id IS NULL OR seller_id <> ?)
+                   ) AS only_seller_items
+            FROM orders
+            WHERE id = ?
+            FOR UPDATE
+            "#,
+        )
+        .bind(caller_id)
+        .bind(caller_id)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_err("Locking order"))?
+        .map(|r| OrderAccess {
+            user_id: r.user_id,
+            status: r.status,
+            has_seller_items: r.has_seller_items != 0,
+            only_seller_items: r.has_seller_items != 0 && r.only_seller_items != 0,
+        });
+
+        check(access.as_ref())?;
+
+        if next == OrderStatus::Cancelled {
+            let lines = sqlx::query_as::<_, (i64, i64)>(
+                r#"
+                SELECT item_id, CAST(SUM(quantity) AS SIGNED)
+                FROM order_items
+                WHERE order_id = ? AND item_id IS NOT NULL
+                GROUP BY item_id
+                ORDER BY item_id
+                "#,
+            )
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(db_err("Loading order lines"))?;
+
+            for (item_id, quantity) in lines {
+                sqlx::query(r#"UPDATE items SET quantity = quantity + ? WHERE id = ?"#)
+                    .bind(quantity)
+                    .bind(item_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_err("Restoring stock"))?;
+            }
+        }
+
+        let status = next.as_str();
+
+        sqlx::query(
+            r#"
+            UPDATE orders
+            SET status = ?,
+                paid_at      = IF(? = 'paid', UTC_TIMESTAMP(), paid_at),
+                shipped_at   = IF(? = 'shipped', UTC_TIMESTAMP(), shipped_at),
+                delivered_at = IF(? = 'delivered', UTC_TIMESTAMP(), delivered_at),
+                cancelled_at = IF(? = 'cancelled', UTC_TIMESTAMP(), cancelled_at),
+                refunded_at  = IF(? = 'refunded', UTC_TIMESTAMP(), refunded_at)
+            WHERE id = ?
+            "#,
+        )
+        .bind(status)
+        .bind(status)
+        .bind(status)
+        .bind(status)
+        .bind(status)
+        .bind(status)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_err("Updating order status"))?;
+
+        let detail = load_order(&mut tx, id).await?;
+
+        tx.commit().await.map_err(db_err("Committing order transition"))?;
+
+        Ok(detail)
+    }
+}
+
+#[async_trait]
+impl HealthRepository for MySqlStore {
+    #[tracing::instrument(name = "db health.ping", skip_all, fields(db.system = "mysql"))]
+    async fn ping(&self) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await.map_err(db_err("Acquiring a connection"))?;
+
+        conn.ping().await.map_err(db_err("Pinging MySQL"))
+    }
+
+    #[tracing::instrument(name = "db health.pending_migrations", skip_all, fields(db.system = "mysql"))]
+    async fn pending_migrations(&self) -> Result<Option<usize>, AppError> {
+        db::pending_migrations(&self.pool).await.map_err(|e| {
+            tracing::error!("Checking migrations failed: {}", e);
+            AppError::Internal("Checking migrations failed.".into())
+        })
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+}
@@ -1,38 +1,91 @@
 use axum::{
-    routing::{get, post, delete},
+    routing::{get, post, patch, delete},
     Router,
 };
-use sqlx::MySqlPool;
-use crate::auth::{login_user, register_user};
+use crate::auth::{change_password, change_user_role, login_user, logout_user, refresh_token, register_user};
+use crate::cart::*;
 use crate::handlers::*;
+use crate::health::{healthz, readyz, version};
+use crate::metrics::{metrics, track_metrics};
+use crate::openapi::openapi_json;
+use crate::request_id::request_id;
+use crate::orders::*;
 use axum::middleware;
 use crate::auth_middleware::{require_auth, require_role};
+use crate::state::AppState;
 
-pub fn create_routes(pool: MySqlPool) -> Router {
+pub fn create_routes(state: AppState) -> Router {
     let public_routes = Router::new()
         .route("/auth/register", post(register_user))
-        .route("/auth/login", post(login_user));
+        .route("/auth/login", post(login_user))
+        .route("/auth/refresh", post(refresh_token))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/version", get(version))
+        .route("/metrics", get(metrics))
+        .route("/openapi.json", get(openapi_json));
+
+    // Reads the document from the route above instead of serving its own.
+    #[cfg(feature = "swagger-ui")]
+    let public_routes = public_routes.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/swagger-ui")
+            .config(utoipa_swagger_ui::Config::from("/openapi.json")),
+    );
 
     let open_routes = Router::new()
+        .route("/auth/logout", post(logout_user))
+        .route("/auth/password", post(change_password))
+        .route("/orders/:id/status", patch(update_order_status))
         .route("/items", get(get_all_items))
         .route("/items/:id", get(get_item))
         .route("/items/category/:id", get(get_items_by_category))
         .route("/categories", get(get_all_categories))
+        .route("/categories/tree", get(get_category_tree))
         .route("/categories/:id", get(get_category_by_id))
+        .route("/categories/:id/path", get(get_category_path))
         .route("/items/search", get(search_items))
         .route("/items/search/category/:category_name", get(get_items_by_category_name));
 
     let protected_routes = Router::new()
+        .route("/me/items", get(get_my_items))
+        .route("/seller/orders", get(get_seller_orders))
         .route("/items/create", post(create_item))
         .route("/items/:id", post(update_item))
+        .route("/items/:id", patch(patch_item))
         .route("/items/:id", delete(delete_item))
+        .route("/categories", post(create_category))
+        .route("/categories/:id", patch(update_category))
+        .route("/categories/:id", delete(delete_category))
         .layer(middleware::from_fn(require_role("seller")));
 
+    let customer_routes = Router::new()
+        .route("/cart", get(get_cart))
+        .route("/cart", delete(clear_cart))
+        .route("/cart/items", post(add_cart_item))
+        .route("/cart/items/:item_id", patch(update_cart_item))
+        .route("/cart/items/:item_id", delete(remove_cart_item))
+        .route("/orders", get(get_my_orders))
+        .route("/orders/:id", get(get_order))
+        .route("/orders/checkout", post(checkout))
+        .route("/orders/:id/cancel", post(cancel_order))
+        .layer(middleware::from_fn(require_role("customer")));
+
+    let admin_routes = Router::new()
+        .route("/users/:id/role", patch(change_user_role))
+        .layer(middleware::from_fn(require_role("admin")));
+
     public_routes
         .merge(
             open_routes
                 .merge(protected_routes)
-                .layer(middleware::from_fn(require_auth)),
+                .merge(customer_routes)
+                .merge(admin_routes)
+                .layer(middleware::from_fn_with_state(state.clone(), require_auth)),
         )
-        .with_state(pool)
+        // Added last so it also times authentication and sees the matched
+        // route template.
+        .layer(middleware::from_fn_with_state(state.clone(), track_metrics))
+        // Outermost, so everything above logs inside the request span.
+        .layer(middleware::from_fn(request_id))
+        .with_state(state)
 }
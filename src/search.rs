@@ -0,0 +1,63 @@
+//! Item search text as the stores understand it.
+//!
+//! The text is split into words. Every word of at least [`MIN_WORD_LEN`]
+//! characters must occur in the name or description, as a whole word or as
+//! the start of one, so `keyb` finds "Keyboard". Results are ordered by
+//! relevance. Text without such words, like `tv` or `5%`, is matched as a
+//! literal substring instead, since the full-text index does not hold words
+//! that short.
+
+/// Shortest word in the full-text index (InnoDB's `innodb_ft_min_token_size`).
+pub const MIN_WORD_LEN: usize = 3;
+
+/// How a search is run.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Lowercased words, each matched as a word prefix.
+    Words(Vec<String>),
+    /// The trimmed text, matched as a substring.
+    Substring(String),
+}
+
+impl SearchMode {
+    pub fn parse(text: &str) -> Self {
+        let mut words: Vec<String> = Vec::new();
+
+        for word in text.split(|c: char| !c.is_alphanumeric()) {
+            let word = word.to_lowercase();
+            if word.chars().count() >= MIN_WORD_LEN && !words.contains(&word) {
+                words.push(word);
+            }
+        }
+
+        if words.is_empty() {
+            SearchMode::Substring(text.trim().to_owned())
+        } else {
+            SearchMode::Words(words)
+        }
+    }
+}
+
+/// MySQL boolean-mode query requiring every word as a prefix: `+keyb* +usb*`.
+/// Words hold only letters and digits, so no operator can slip in.
+pub fn boolean_query(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|w| format!("+{}*", w))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `LIKE` pattern matching `text` literally anywhere in a column.
+pub fn like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
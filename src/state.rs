@@ -0,0 +1,56 @@
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::repo::{
+    CartRepository, CategoryRepository, HealthRepository, ItemRepository, MemoryStore, MySqlStore,
+    OrderRepository, UserRepository,
+};
+use sqlx::MySqlPool;
+use std::sync::Arc;
+
+/// State shared by every handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub items: Arc<dyn ItemRepository>,
+    pub categories: Arc<dyn CategoryRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub carts: Arc<dyn CartRepository>,
+    pub orders: Arc<dyn OrderRepository>,
+    pub health: Arc<dyn HealthRepository>,
+    pub config: Arc<Config>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl AppState {
+    /// Production state backed by MySQL.
+    pub fn mysql(pool: MySqlPool, config: Config) -> Self {
+        Self::from_store(Arc::new(MySqlStore::new(pool)), config)
+    }
+
+    /// State kept in process memory, starting with the seeded roles and no
+    /// other data. Meant for tests.
+    pub fn in_memory(config: Config) -> Self {
+        Self::from_store(Arc::new(MemoryStore::new()), config)
+    }
+
+    fn from_store<S>(store: Arc<S>, config: Config) -> Self
+    where
+        S: ItemRepository
+            + CategoryRepository
+            + UserRepository
+            + CartRepository
+            + OrderRepository
+            + HealthRepository
+            + 'static,
+    {
+        Self {
+            items: store.clone(),
+            categories: store.clone(),
+            users: store.clone(),
+            carts: store.clone(),
+            orders: store.clone(),
+            health: store,
+            config: Arc::new(config),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+}
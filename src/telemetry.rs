@@ -0,0 +1,99 @@
+//! Log output and, when enabled, trace export over OTLP, set up once the
+//! configuration is loaded.
+
+use crate::config::{LogConfig, LogFormat, OtelConfig};
+use axum::http::HeaderMap;
+use opentelemetry::{propagation::Extractor, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+/// Flushes buffered spans when dropped; keep it alive until shutdown.
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Flushing traces failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. The filter was validated with the rest of
+/// the configuration. Fails only when the OTLP exporter cannot be built.
+pub fn init(log: &LogConfig, otel: &OtelConfig) -> Result<Telemetry, String> {
+    let filter = EnvFilter::try_new(&log.filter).unwrap_or_else(|_| EnvFilter::new("info"));
+
+    // `traceparent` is read from incoming requests whether or not spans are
+    // exported, so ids stay consistent across services.
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = if otel.enabled {
+        Some(tracer_provider(otel)?)
+    } else {
+        None
+    };
+
+    let otel_layer = provider.as_ref().map(|p| {
+        tracing_opentelemetry::layer().with_tracer(p.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    let registry = tracing_subscriber::registry().with(filter).with(otel_layer);
+
+    match log.format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        // Fields of the enclosing request span (request id, route, user) are
+        // flattened into `span` on every line.
+        LogFormat::Json => registry
+            .with(fmt::layer().json().with_current_span(true).with_span_list(false))
+            .init(),
+    }
+
+    if otel.enabled {
+        tracing::info!("Exporting traces to {}", otel.endpoint);
+    }
+
+    Ok(Telemetry { provider })
+}
+
+fn tracer_provider(otel: &OtelConfig) -> Result<TracerProvider, String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpBinary)
+        .with_endpoint(format!("{}/v1/traces", otel.endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|e| format!("cannot create the OTLP exporter: {}", e))?;
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            otel.sample_ratio,
+        ))))
+        .with_resource(Resource::new(vec![
+            KeyValue::new("service.name", otel.service_name.clone()),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build())
+}
+
+/// Reads W3C trace context from request headers.
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
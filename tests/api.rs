@@ -0,0 +1,1547 @@
+//! End-to-end tests of the HTTP API. Every test builds the full router on a
+//! fresh in-memory store, so no database or other service is needed.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use store::{config::Config, routes::create_routes, state::AppState};
+use tower::ServiceExt;
+
+fn config() -> Config {
+    let mut config = Config::default();
+    config.database.url = "mysql://unused/test".into();
+    config.auth.jwt_secret = "test-secret-that-is-long-enough-for-hs256".into();
+    // The cheapest cost bcrypt accepts, so registering users stays fast.
+    config.auth.bcrypt_cost = 4;
+    config
+}
+
+fn app() -> Router {
+    create_routes(AppState::in_memory(config()))
+}
+
+/// Sends one request through the router and returns the status and the
+/// JSON body (`Value::Null` when the body is empty).
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+
+    // Plain-text bodies such as `/metrics` come back as a JSON string.
+    let body = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+    };
+
+    (status, body)
+}
+
+async fn register(app: &Router, username: &str, role: &str) -> (StatusCode, Value) {
+    send(
+        app,
+        Method::POST,
+        "/auth/register",
+        None,
+        Some(json!({ "username": username, "password": "hunter22", "role": role })),
+    )
+    .await
+}
+
+/// Registers a user with the given role and returns an access token.
+async fn login_as(app: &Router, username: &str, role: &str) -> String {
+    let (status, _) = register(app, username, role).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": username, "password": "hunter22" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+async fn create_item(app: &Router, token: &str, name: &str, amount: &str, quantity: i32) -> Value {
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/items/create",
+        Some(token),
+        Some(json!({
+            "name": name,
+            "price": { "amount": amount, "currency": "EUR" },
+            "quantity": quantity,
+            "category_id": null
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    body
+}
+
+fn error_fields(body: &Value) -> Vec<&str> {
+    body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn register_login_and_use_token() {
+    let app = app();
+
+    let (status, user) = register(&app, "alice", "customer").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["username"], "alice");
+    assert!(user.get("password_hash").is_none());
+
+    let (status, login) = send(
+        &app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": "alice", "password": "hunter22" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(login["token_type"], "Bearer");
+    assert_eq!(login["expires_in"], 20 * 60);
+    assert!(login["refresh_token"].is_string());
+
+    let token = login["access_token"].as_str().unwrap();
+
+    let (status, listing) = send(&app, Method::GET, "/items", Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(listing["items"], json!([]));
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let app = app();
+
+    let (status, body) = send(&app, Method::GET, "/items", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    let (status, _) = send(&app, Method::GET, "/items", Some("not-a-jwt"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn login_rejects_a_wrong_password_and_unknown_user() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": "alice", "password": "wrong" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": "nobody", "password": "hunter22" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn register_validates_role_and_username() {
+    let app = app();
+
+    let (status, body) = register(&app, "bob", "wizard").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["role"]);
+
+    let (status, _) = register(&app, "bob", "admin").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = register(&app, "bob", "seller").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = register(&app, "bob", "customer").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "username_taken");
+}
+
+/// Logs in an already registered user and returns the access and refresh
+/// tokens.
+async fn login(app: &Router, username: &str, password: &str) -> (String, String) {
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": username, "password": password })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    (
+        body["access_token"].as_str().unwrap().to_string(),
+        body["refresh_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn refresh(app: &Router, refresh_token: &str) -> (StatusCode, Value) {
+    send(
+        app,
+        Method::POST,
+        "/auth/refresh",
+        None,
+        Some(json!({ "refresh_token": refresh_token })),
+    )
+    .await
+}
+
+/// Whether the access token still gets through `require_auth`.
+async fn token_works(app: &Router, token: &str) -> bool {
+    let (status, _) = send(app, Method::GET, "/items", Some(token), None).await;
+    status == StatusCode::OK
+}
+
+#[tokio::test]
+async fn refresh_rotates_the_token_pair() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (_, first) = login(&app, "alice", "hunter22").await;
+
+    let (status, pair) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::OK, "{}", pair);
+    assert_eq!(pair["token_type"], "Bearer");
+    let second = pair["refresh_token"].as_str().unwrap();
+    assert_ne!(second, first);
+    assert!(token_works(&app, pair["access_token"].as_str().unwrap()).await);
+
+    let (status, pair) = refresh(&app, second).await;
+    assert_eq!(status, StatusCode::OK, "{}", pair);
+}
+
+#[tokio::test]
+async fn reusing_a_refresh_token_revokes_its_whole_family() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (_, first) = login(&app, "alice", "hunter22").await;
+    let (other_access, other_refresh) = login(&app, "alice", "hunter22").await;
+
+    let (status, pair) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::OK);
+    let newest = pair["refresh_token"].as_str().unwrap();
+
+    // Someone replays the rotated token.
+    let (status, body) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    // The legitimate holder is logged out too, since either of them may be
+    // the thief.
+    let (status, _) = refresh(&app, newest).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Sessions from other logins are not affected.
+    assert!(token_works(&app, &other_access).await);
+    let (status, _) = refresh(&app, &other_refresh).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn logout_deny_lists_only_the_presented_token() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (phone, _) = login(&app, "alice", "hunter22").await;
+    let (laptop, laptop_refresh) = login(&app, "alice", "hunter22").await;
+
+    let (status, body) = send(&app, Method::POST, "/auth/logout", Some(&phone), None).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    assert!(!token_works(&app, &phone).await);
+    assert!(token_works(&app, &laptop).await);
+
+    let (status, _) = refresh(&app, &laptop_refresh).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn logout_rejects_a_malformed_body_and_revokes_nothing() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (phone, _) = login(&app, "alice", "hunter22").await;
+    let (laptop, _) = login(&app, "alice", "hunter22").await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/auth/logout",
+        Some(&phone),
+        Some(json!({ "everywhere": "true" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+
+    assert!(token_works(&app, &phone).await);
+    assert!(token_works(&app, &laptop).await);
+}
+
+#[tokio::test]
+async fn logout_revokes_the_given_refresh_token_or_everything() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (phone, phone_refresh) = login(&app, "alice", "hunter22").await;
+    let (laptop, laptop_refresh) = login(&app, "alice", "hunter22").await;
+    let (tablet, tablet_refresh) = login(&app, "alice", "hunter22").await;
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/auth/logout",
+        Some(&phone),
+        Some(json!({ "refresh_token": phone_refresh })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = refresh(&app, &phone_refresh).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(token_works(&app, &laptop).await);
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/auth/logout",
+        Some(&laptop),
+        Some(json!({ "everywhere": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(!token_works(&app, &tablet).await);
+    for refresh_token in [&laptop_refresh, &tablet_refresh] {
+        let (status, _) = refresh(&app, refresh_token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    // Logging in again starts a fresh session.
+    let (fresh, _) = login(&app, "alice", "hunter22").await;
+    assert!(token_works(&app, &fresh).await);
+}
+
+#[tokio::test]
+async fn changing_the_password_revokes_every_token() {
+    let app = app();
+    register(&app, "alice", "customer").await;
+    let (phone, phone_refresh) = login(&app, "alice", "hunter22").await;
+    let (laptop, _) = login(&app, "alice", "hunter22").await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/auth/password",
+        Some(&laptop),
+        Some(json!({ "current_password": "hunter22", "new_password": "correct-horse" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    assert!(!token_works(&app, &phone).await);
+    assert!(!token_works(&app, &laptop).await);
+    let (status, _) = refresh(&app, &phone_refresh).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (fresh, _) = login(&app, "alice", "correct-horse").await;
+    assert!(token_works(&app, &fresh).await);
+}
+
+#[tokio::test]
+async fn changing_the_role_revokes_every_token() {
+    let state = AppState::in_memory(config());
+    let app = create_routes(state.clone());
+    let admin = admin_token(&app, &state).await;
+
+    let (_, user) = register(&app, "alice", "customer").await;
+    let (access, refresh_token) = login(&app, "alice", "hunter22").await;
+
+    let (status, body) = send(
+        &app,
+        Method::PATCH,
+        &format!("/users/{}/role", user["id"]),
+        Some(&admin),
+        Some(json!({ "role": "seller" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    assert!(!token_works(&app, &access).await);
+    let (status, _) = refresh(&app, &refresh_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // A new login carries the new role.
+    let (seller, _) = login(&app, "alice", "hunter22").await;
+    let (status, _) = send(&app, Method::GET, "/me/items", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn seller_routes_require_the_seller_role() {
+    let app = app();
+    let customer = login_as(&app, "carol", "customer").await;
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let item = json!({
+        "name": "Milk",
+        "price": { "amount": "1.49", "currency": "EUR" },
+        "quantity": 5,
+        "category_id": null
+    });
+
+    let (status, body) = send(&app, Method::POST, "/items/create", Some(&customer), Some(item.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "forbidden");
+
+    let (status, _) = send(&app, Method::GET, "/me/items", Some(&customer), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, created) = send(&app, Method::POST, "/items/create", Some(&seller), Some(item)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, mine) = send(&app, Method::GET, "/me/items", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(mine["items"], json!([created]));
+}
+
+#[tokio::test]
+async fn item_create_validates_every_field() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/items/create",
+        Some(&seller),
+        Some(json!({
+            "name": " a ",
+            "price": { "amount": "-1.00", "currency": "EUR" },
+            "quantity": 0,
+            "category_id": 42
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(error_fields(&body), ["name", "price", "quantity", "category_id"]);
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/items/create",
+        Some(&seller),
+        Some(json!({
+            "name": "Rice",
+            "price": { "amount": "1.005", "currency": "EUR" },
+            "quantity": 1,
+            "category_id": null
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["price"]);
+
+    // Prices are stored as DECIMAL(19,4).
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/items/create",
+        Some(&seller),
+        Some(json!({
+            "name": "Yacht",
+            "price": { "amount": "1000000000000000.00", "currency": "EUR" },
+            "quantity": 1,
+            "category_id": null
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["errors"][0]["message"], "must be at most 999999999999999.9999");
+}
+
+#[tokio::test]
+async fn item_crud_round_trip() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let (status, category) = send(
+        &app,
+        Method::POST,
+        "/categories",
+        Some(&seller),
+        Some(json!({ "name": "Dairy" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let uri = format!("/items/{}", item["id"]);
+    assert_eq!(item["price"], json!({ "amount": "1.49", "currency": "EUR" }));
+
+    let (status, fetched) = send(&app, Method::GET, &uri, Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, item);
+
+    let (status, patched) = send(
+        &app,
+        Method::PATCH,
+        &uri,
+        Some(&seller),
+        Some(json!({ "quantity": 9, "category_id": category["id"] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(patched["quantity"], 9);
+    assert_eq!(patched["name"], "Milk");
+    assert_eq!(patched["category_id"], category["id"]);
+
+    let (status, body) = send(&app, Method::PATCH, &uri, Some(&seller), Some(json!({ "quantity": 0 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["quantity"]);
+
+    let (status, updated) = send(
+        &app,
+        Method::POST,
+        &uri,
+        Some(&seller),
+        Some(json!({
+            "name": "Whole milk",
+            "price": { "amount": "1.99", "currency": "EUR" },
+            "quantity": 3,
+            "category_id": null
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updated["name"], "Whole milk");
+    assert_eq!(updated["category_id"], Value::Null);
+
+    let (status, _) = send(&app, Method::DELETE, &uri, Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&app, Method::GET, &uri, Some(&seller), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn items_can_only_be_changed_by_their_owner() {
+    let app = app();
+    let owner = login_as(&app, "sam", "seller").await;
+    let other = login_as(&app, "sue", "seller").await;
+
+    let item = create_item(&app, &owner, "Milk", "1.49", 5).await;
+    let uri = format!("/items/{}", item["id"]);
+
+    let (status, body) = send(&app, Method::PATCH, &uri, Some(&other), Some(json!({ "quantity": 1 }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "forbidden");
+
+    let (status, _) = send(&app, Method::DELETE, &uri, Some(&other), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn search_is_paginated() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    for i in 1..=5 {
+        create_item(&app, &seller, &format!("Milk {}", i), "1.00", 1).await;
+    }
+    create_item(&app, &seller, "Bread", "2.00", 1).await;
+
+    let names = |body: &Value| -> Vec<String> {
+        body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let (status, page) = send(&app, Method::GET, "/items/search?name=milk&page=1&page_size=2", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(names(&page), ["Milk 1", "Milk 2"]);
+
+    let (_, page) = send(&app, Method::GET, "/items/search?name=milk&page=3&page_size=2", Some(&seller), None).await;
+    assert_eq!(names(&page), ["Milk 5"]);
+
+    let (_, page) = send(&app, Method::GET, "/items/search?name=milk&page=4&page_size=2", Some(&seller), None).await;
+    assert_eq!(page["items"], json!([]));
+    assert_eq!(page["total"], 5);
+
+    let (_, page) = send(&app, Method::GET, "/items/search?name=bread", Some(&seller), None).await;
+    assert_eq!(names(&page), ["Bread"]);
+
+    let (status, body) = send(&app, Method::GET, "/items/search?name=m", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["name"]);
+
+    let (status, body) = send(&app, Method::GET, "/items/search?name=milk&page=0", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["page"]);
+}
+
+/// GETs `uri` and returns the status, the `Link` header and the JSON body.
+async fn get_page(app: &Router, uri: &str, token: &str) -> (StatusCode, String, Value) {
+    let request = Request::builder()
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let link = response
+        .headers()
+        .get(header::LINK)
+        .map(|v| v.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, link, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn list_endpoints_page_with_cursors() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let mut ids = Vec::new();
+    for (name, amount) in [("Apple", "3.00"), ("Bread", "1.00"), ("Cheese", "5.00"), ("Donut", "2.00"), ("Eggs", "4.00")] {
+        ids.push(create_item(&app, &seller, name, amount, 1).await["id"].clone());
+    }
+    let names = |page: &Value| -> Vec<String> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let (status, link, first) = get_page(&app, "/me/items?page_size=2", &seller).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(names(&first), ["Apple", "Bread"]);
+    assert_eq!(first["total"], 5);
+    let cursor = first["next_cursor"].as_str().unwrap().to_string();
+    assert_eq!(
+        link,
+        format!("</me/items?page_size=2>; rel=\"first\", </me/items?page_size=2&cursor={}>; rel=\"next\"", cursor)
+    );
+
+    // Removing a row of an earlier page does not shift the next one.
+    let (status, _) = send(&app, Method::DELETE, &format!("/items/{}", ids[0]), Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, _, second) = get_page(&app, &format!("/me/items?page_size=2&cursor={}", cursor), &seller).await;
+    assert_eq!(names(&second), ["Cheese", "Donut"]);
+    assert_eq!(second["total"], 4);
+
+    let cursor = second["next_cursor"].as_str().unwrap();
+    let (_, link, last) = get_page(&app, &format!("/me/items?page_size=2&cursor={}", cursor), &seller).await;
+    assert_eq!(names(&last), ["Eggs"]);
+    assert_eq!(last["next_cursor"], Value::Null);
+    assert_eq!(link, "</me/items?page_size=2>; rel=\"first\"");
+
+    // Sorted listings page on the sort key.
+    let (_, _, cheap) = get_page(&app, "/items?sort=price&currency=EUR&page_size=3", &seller).await;
+    assert_eq!(names(&cheap), ["Bread", "Donut", "Eggs"]);
+    let cursor = cheap["next_cursor"].as_str().unwrap().to_string();
+    let (_, _, rest) = get_page(&app, &format!("/items?sort=price&currency=EUR&page_size=3&cursor={}", cursor), &seller).await;
+    assert_eq!(names(&rest), ["Cheese"]);
+
+    // A cursor only fits the sort it came from.
+    let (status, _, body) = get_page(&app, &format!("/items?sort=name&cursor={}", cursor), &seller).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["cursor"]);
+
+    for uri in ["/categories?page_size=101", "/me/items?page_size=0", "/items?cursor=bogus"] {
+        let (status, _, body) = get_page(&app, uri, &seller).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(error_fields(&body).len(), 1, "{}", uri);
+    }
+
+    let (status, _, orders) = get_page(&app, "/seller/orders", &seller).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(orders, json!({ "items": [], "total": 0, "next_cursor": null }));
+}
+
+#[tokio::test]
+async fn search_matches_word_prefixes_in_name_and_description() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    for (name, description) in [
+        ("USB cable", json!("Braided, 2 m. Fits any keyboard.")),
+        ("Mechanical keyboard", json!("Keyboard with USB-C and keyboard shortcuts")),
+        ("Monitor 50% off", Value::Null),
+        ("Monitor 500", json!("Matte panel")),
+    ] {
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/items/create",
+            Some(&seller),
+            Some(json!({
+                "name": name,
+                "description": description,
+                "price": { "amount": "9.99", "currency": "EUR" },
+                "quantity": 1,
+                "category_id": null
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let search = |query: &'static str| {
+        let app = app.clone();
+        let seller = seller.clone();
+        async move {
+            let (status, body) = send(&app, Method::GET, &format!("/items/search?name={}", query), Some(&seller), None).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // Partial words, in either field, most matches first.
+    assert_eq!(search("keyb").await, ["Mechanical keyboard", "USB cable"]);
+    // Every word has to match, in any order.
+    assert_eq!(search("usb+mech").await, ["Mechanical keyboard"]);
+    assert_eq!(search("matte+monitor").await, ["Monitor 500"]);
+    // Too short for the index: a literal substring, so `%` is no wildcard.
+    assert_eq!(search("0%25").await, ["Monitor 50% off"]);
+    assert_eq!(search("__").await, Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn listing_filters_sorts_and_counts_facets() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let mut categories = Vec::new();
+    for name in ["Dairy", "Bakery"] {
+        let (_, category) = send(&app, Method::POST, "/categories", Some(&seller), Some(json!({ "name": name }))).await;
+        categories.push(category["id"].clone());
+    }
+    let (dairy, bakery) = (&categories[0], &categories[1]);
+
+    let mut ids = Vec::new();
+    for (name, amount, quantity, category) in [
+        ("Milk", "1.49", 10, dairy.clone()),
+        ("Cheese", "12.00", 4, dairy.clone()),
+        ("Bread", "2.50", 5, bakery.clone()),
+        ("Cake", "30.00", 2, bakery.clone()),
+        ("Knife", "120.00", 1, Value::Null),
+    ] {
+        let (status, item) = send(
+            &app,
+            Method::POST,
+            "/items/create",
+            Some(&seller),
+            Some(json!({
+                "name": name,
+                "price": { "amount": amount, "currency": "EUR" },
+                "quantity": quantity,
+                "category_id": category
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", item);
+        ids.push(item["id"].clone());
+    }
+
+    // Three loaves and every cake are sold.
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/orders/checkout",
+        Some(&customer),
+        Some(json!({ "lines": [
+            { "item_id": ids[2], "quantity": 3 },
+            { "item_id": ids[3], "quantity": 2 }
+        ] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let list = |query: String| {
+        let app = app.clone();
+        let seller = seller.clone();
+        async move {
+            let (status, body) = send(&app, Method::GET, &format!("/items{}", query), Some(&seller), None).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            body
+        }
+    };
+    let names = |listing: &Value| -> Vec<String> {
+        listing["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let counts = |facet: &Value| -> Vec<i64> {
+        facet.as_array().unwrap().iter().map(|f| f["count"].as_i64().unwrap()).collect()
+    };
+
+    let all = list(String::new()).await;
+    assert_eq!(names(&all), ["Milk", "Cheese", "Bread", "Cake", "Knife"]);
+    assert_eq!(
+        all["facets"]["categories"],
+        json!([
+            { "category_id": dairy, "name": "Dairy", "count": 2 },
+            { "category_id": bakery, "name": "Bakery", "count": 2 },
+            { "category_id": null, "name": null, "count": 1 }
+        ])
+    );
+    // Amounts in different currencies do not compare.
+    assert_eq!(all["facets"]["price"], json!([]));
+
+    let euros = list("?currency=EUR".into()).await;
+    assert_eq!(euros["facets"]["price"][1], json!({ "min": "10", "max": "25", "count": 1 }));
+    assert_eq!(euros["facets"]["price"][4]["max"], Value::Null);
+    assert_eq!(counts(&euros["facets"]["price"]), [2, 1, 1, 0, 1]);
+    assert_eq!(counts(&list("?currency=USD".into()).await["facets"]["price"]), [0, 0, 0, 0, 0]);
+
+    // Each facet ignores its own filter but honours the others.
+    let filtered = list(format!(
+        "?category={},{}&in_stock=true&currency=EUR&sort=price&order=desc",
+        dairy, bakery
+    ))
+    .await;
+    assert_eq!(names(&filtered), ["Cheese", "Bread", "Milk"]);
+    assert_eq!(counts(&filtered["facets"]["categories"]), [2, 1, 1]);
+    assert_eq!(counts(&filtered["facets"]["price"]), [2, 1, 0, 0, 0]);
+
+    let priced = list("?min_price=2&max_price=50&currency=EUR&sort=name".into()).await;
+    assert_eq!(names(&priced), ["Bread", "Cake", "Cheese"]);
+    assert_eq!(counts(&priced["facets"]["price"]), [2, 1, 1, 0, 1]);
+
+    assert_eq!(names(&list("?sort=popularity".into()).await), ["Bread", "Cake", "Knife", "Cheese", "Milk"]);
+    assert_eq!(names(&list("?sort=popularity&order=asc".into()).await), ["Milk", "Cheese", "Knife", "Cake", "Bread"]);
+    assert_eq!(names(&list("?sort=newest".into()).await), ["Knife", "Cake", "Bread", "Cheese", "Milk"]);
+    assert_eq!(names(&list("?q=brea".into()).await), ["Bread"]);
+
+    // Only the known sort keys parse.
+    let (status, _) = send(&app, Method::GET, "/items?sort=price;DROP", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    for (query, fields) in [
+        ("min_price=5&max_price=1&currency=EUR", vec!["max_price"]),
+        ("max_price=-1&currency=EUR", vec!["max_price"]),
+        ("min_price=-1&currency=EUR", vec!["min_price"]),
+        ("min_price=5", vec!["currency"]),
+        ("max_price=5", vec!["currency"]),
+        ("sort=price", vec!["currency"]),
+    ] {
+        let (status, body) = send(&app, Method::GET, &format!("/items?{}", query), Some(&seller), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        assert_eq!(error_fields(&body), fields, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn categories_nest_into_a_tree() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let create = |name: &'static str, parent_id: Value| {
+        let app = app.clone();
+        let seller = seller.clone();
+        async move {
+            send(&app, Method::POST, "/categories", Some(&seller), Some(json!({ "name": name, "parent_id": parent_id }))).await
+        }
+    };
+
+    let (status, dairy) = create("Dairy", Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(dairy["parent_id"], Value::Null);
+    let (_, milk) = create("Milk", dairy["id"].clone()).await;
+    let (_, lactose_free) = create("Lactose-free", milk["id"].clone()).await;
+    let (_, bakery) = create("Bakery", Value::Null).await;
+
+    // Names are unique among siblings only.
+    let (status, body) = create("milk", dairy["id"].clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "category_name_taken");
+    let (status, _) = create("Milk", bakery["id"].clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = create("Cheese", json!(999)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["parent_id"]);
+
+    let (_, tree) = send(&app, Method::GET, "/categories/tree", Some(&seller), None).await;
+    fn shape(nodes: &Value) -> Value {
+        nodes
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| json!([node["name"], shape(&node["children"])]))
+            .collect()
+    }
+    assert_eq!(
+        shape(&tree),
+        json!([["Bakery", [["Milk", []]]], ["Dairy", [["Milk", [["Lactose-free", []]]]]]])
+    );
+
+    let (status, path) = send(&app, Method::GET, &format!("/categories/{}/path", lactose_free["id"]), Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(path, json!([dairy, milk, lactose_free]));
+    let (status, _) = send(&app, Method::GET, "/categories/999/path", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for (name, category) in [("Gouda", &dairy), ("Whole milk", &milk), ("Oat milk", &lactose_free)] {
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/items/create",
+            Some(&seller),
+            Some(json!({
+                "name": name,
+                "price": { "amount": "1.00", "currency": "EUR" },
+                "quantity": 1,
+                "category_id": category["id"]
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let total = |uri: String| {
+        let app = app.clone();
+        let seller = seller.clone();
+        async move {
+            let (status, body) = send(&app, Method::GET, &uri, Some(&seller), None).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            body["total"].as_i64().unwrap()
+        }
+    };
+    assert_eq!(total(format!("/items/category/{}", dairy["id"])).await, 1);
+    assert_eq!(total(format!("/items/category/{}?include_descendants=true", dairy["id"])).await, 3);
+    assert_eq!(total(format!("/items?category={}&include_descendants=true", milk["id"])).await, 2);
+    assert_eq!(total("/items/category/999?include_descendants=true".into()).await, 0);
+
+    // A category cannot move under itself or below its own subtree.
+    for parent in [&dairy, &lactose_free] {
+        let (status, body) = send(
+            &app,
+            Method::PATCH,
+            &format!("/categories/{}", dairy["id"]),
+            Some(&seller),
+            Some(json!({ "parent_id": parent["id"] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error_fields(&body), ["parent_id"]);
+    }
+
+    let (status, moved) = send(
+        &app,
+        Method::PATCH,
+        &format!("/categories/{}", lactose_free["id"]),
+        Some(&seller),
+        Some(json!({ "parent_id": null })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(moved["parent_id"], Value::Null);
+    assert_eq!(moved["name"], "Lactose-free");
+    assert_eq!(total(format!("/items/category/{}?include_descendants=true", dairy["id"])).await, 2);
+
+    let (status, body) = send(&app, Method::DELETE, &format!("/categories/{}", bakery["id"]), Some(&seller), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "category_has_children");
+}
+
+#[tokio::test]
+async fn missing_resources_return_404() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    for uri in ["/items/999", "/categories/999", "/items/search/category/Nope"] {
+        let (status, body) = send(&app, Method::GET, uri, Some(&seller), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["status"], 404);
+    }
+
+    let (status, _) = send(&app, Method::PATCH, "/items/999", Some(&seller), Some(json!({ "quantity": 1 }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&app, Method::DELETE, "/items/999", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&app, Method::DELETE, "/categories/999", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn malformed_requests_get_problem_documents() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+
+    let bad_body = json!({
+        "name": "Rice",
+        "price": { "amount": "1.00", "currency": "EUR" },
+        "quantity": "2",
+        "category_id": null
+    });
+    for (method, uri, body) in [
+        (Method::POST, "/items/create", Some(bad_body)),
+        (Method::GET, "/items/abc", None),
+        (Method::GET, "/items?sort=cheapest", None),
+    ] {
+        let (status, body) = send(&app, method, uri, Some(&seller), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(body["code"], "bad_request", "{}", uri);
+        assert_eq!(body["status"], 400, "{}", uri);
+    }
+
+    // Not JSON at all, and without a content type.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/auth/login")
+        .body(Body::from("username=sam"))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/problem+json");
+}
+
+#[tokio::test]
+async fn checkout_takes_stock_and_cancel_puts_it_back() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let item_uri = format!("/items/{}", item["id"]);
+
+    let (status, cart) = send(
+        &app,
+        Method::POST,
+        "/cart/items",
+        Some(&customer),
+        Some(json!({ "item_id": item["id"], "quantity": 2 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cart["total"], json!({ "amount": "2.98", "currency": "EUR" }));
+
+    let (status, order) = send(&app, Method::POST, "/orders/checkout", Some(&customer), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(order["status"], "pending");
+    assert_eq!(order["total"]["amount"], "2.98");
+
+    let (_, item) = send(&app, Method::GET, &item_uri, Some(&customer), None).await;
+    assert_eq!(item["quantity"], 3);
+
+    let (_, cart) = send(&app, Method::GET, "/cart", Some(&customer), None).await;
+    assert_eq!(cart["lines"], json!([]));
+
+    let (status, cancelled) = send(
+        &app,
+        Method::POST,
+        &format!("/orders/{}/cancel", order["id"]),
+        Some(&customer),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cancelled["status"], "cancelled");
+
+    let (_, item) = send(&app, Method::GET, &item_uri, Some(&customer), None).await;
+    assert_eq!(item["quantity"], 5);
+}
+
+/// Adds an admin straight to the store, since `/auth/register` refuses
+/// the role, and returns an access token.
+async fn admin_token(app: &Router, state: &AppState) -> String {
+    let role = state.users.find_role("admin").await.unwrap().unwrap();
+    let hash = bcrypt::hash("hunter22", 4).unwrap();
+    state.users.create("root", &hash, role.id).await.unwrap();
+
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": "root", "password": "hunter22" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Checks out `lines` of `(item, quantity)` and returns the order's
+/// status URI.
+async fn place_order(app: &Router, token: &str, lines: &[(&Value, i32)]) -> String {
+    let lines: Vec<Value> = lines
+        .iter()
+        .map(|(item, quantity)| json!({ "item_id": item["id"], "quantity": quantity }))
+        .collect();
+
+    let (status, order) = send(
+        app,
+        Method::POST,
+        "/orders/checkout",
+        Some(token),
+        Some(json!({ "lines": lines })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", order);
+
+    format!("/orders/{}/status", order["id"])
+}
+
+async fn move_order(app: &Router, uri: &str, token: &str, status: &str) -> (StatusCode, Value) {
+    send(app, Method::PATCH, uri, Some(token), Some(json!({ "status": status }))).await
+}
+
+#[tokio::test]
+async fn order_moves_follow_the_lifecycle_and_roles() {
+    let state = AppState::in_memory(config());
+    let app = create_routes(state.clone());
+    let admin = admin_token(&app, &state).await;
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let milk = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let order = place_order(&app, &customer, &[(&milk, 1)]).await;
+
+    // Nobody but an admin records a payment.
+    for token in [&customer, &seller] {
+        let (status, body) = move_order(&app, &order, token, "paid").await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    }
+    let (status, body) = move_order(&app, &order, &seller, "shipped").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "illegal_transition");
+
+    let (status, body) = move_order(&app, &order, &admin, "paid").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "paid");
+
+    // Once paid, the buyer would be owed a refund.
+    let (status, _) = move_order(&app, &order, &customer, "cancelled").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let cancel = order.replace("/status", "/cancel");
+    let (status, _) = send(&app, Method::POST, &cancel, Some(&customer), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = move_order(&app, &order, &customer, "shipped").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = move_order(&app, &order, &seller, "shipped").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = move_order(&app, &order, &seller, "delivered").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = move_order(&app, &order, &seller, "refunded").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = move_order(&app, &order, &admin, "refunded").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = move_order(&app, &order, &admin, "pending").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "illegal_transition");
+}
+
+#[tokio::test]
+async fn paid_orders_are_cancelled_by_admins_only() {
+    let state = AppState::in_memory(config());
+    let app = create_routes(state.clone());
+    let admin = admin_token(&app, &state).await;
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let milk = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let milk_uri = format!("/items/{}", milk["id"]);
+
+    // A pending order is still the buyer's to cancel.
+    let order = place_order(&app, &customer, &[(&milk, 1)]).await;
+    let (status, _) = move_order(&app, &order, &customer, "cancelled").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let order = place_order(&app, &customer, &[(&milk, 2)]).await;
+    move_order(&app, &order, &admin, "paid").await;
+    let (status, _) = move_order(&app, &order, &customer, "cancelled").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (_, item) = send(&app, Method::GET, &milk_uri, Some(&seller), None).await;
+    assert_eq!(item["quantity"], 3);
+
+    let (status, body) = move_order(&app, &order, &admin, "cancelled").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "cancelled");
+
+    let (_, item) = send(&app, Method::GET, &milk_uri, Some(&seller), None).await;
+    assert_eq!(item["quantity"], 5);
+}
+
+#[tokio::test]
+async fn orders_with_several_sellers_are_shipped_by_admins() {
+    let state = AppState::in_memory(config());
+    let app = create_routes(state.clone());
+    let admin = admin_token(&app, &state).await;
+    let sam = login_as(&app, "sam", "seller").await;
+    let sue = login_as(&app, "sue", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let milk = create_item(&app, &sam, "Milk", "1.49", 5).await;
+    let bread = create_item(&app, &sue, "Bread", "2.10", 5).await;
+    let order = place_order(&app, &customer, &[(&milk, 1), (&bread, 1)]).await;
+    move_order(&app, &order, &admin, "paid").await;
+
+    for seller in [&sam, &sue] {
+        let (status, body) = move_order(&app, &order, seller, "shipped").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "forbidden");
+    }
+
+    let (status, _) = move_order(&app, &order, &admin, "shipped").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = move_order(&app, &order, &sam, "delivered").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // A seller without items in the order does not even see it.
+    let other = login_as(&app, "sid", "seller").await;
+    let (status, _) = move_order(&app, &order, &other, "delivered").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn editing_an_item_keeps_the_stock_checkout_took() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let item_uri = format!("/items/{}", item["id"]);
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/orders/checkout",
+        Some(&customer),
+        Some(json!({ "lines": [{ "item_id": item["id"], "quantity": 2 }] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Only the fields in the body are written, so the stock the seller
+    // last saw (5) does not overwrite what checkout left.
+    let (status, patched) = send(
+        &app,
+        Method::PATCH,
+        &item_uri,
+        Some(&seller),
+        Some(json!({ "name": "Whole milk", "description": null })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(patched["name"], "Whole milk");
+    assert_eq!(patched["quantity"], 3);
+
+    let (_, fetched) = send(&app, Method::GET, &item_uri, Some(&seller), None).await;
+    assert_eq!(fetched, patched);
+}
+
+#[tokio::test]
+async fn checkout_with_a_malformed_body_leaves_the_cart_alone() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/cart/items",
+        Some(&customer),
+        Some(json!({ "item_id": item["id"], "quantity": 2 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/orders/checkout",
+        Some(&customer),
+        Some(json!({ "lines": [{ "item_id": item["id"], "quantity": "1" }] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+
+    // A body without a content type is not taken for an empty one either.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/orders/checkout")
+        .header(header::AUTHORIZATION, format!("Bearer {}", customer))
+        .body(Body::from("lines"))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let (_, cart) = send(&app, Method::GET, "/cart", Some(&customer), None).await;
+    assert_eq!(cart["lines"][0]["quantity"], 2);
+    let (_, item) = send(&app, Method::GET, &format!("/items/{}", item["id"]), Some(&customer), None).await;
+    assert_eq!(item["quantity"], 5);
+}
+
+#[tokio::test]
+async fn checkout_rejects_overflowing_quantities() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/orders/checkout",
+        Some(&customer),
+        Some(json!({ "lines": [
+            { "item_id": item["id"], "quantity": i32::MAX },
+            { "item_id": item["id"], "quantity": 2 }
+        ] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["lines"]);
+
+    let (_, item) = send(&app, Method::GET, &format!("/items/{}", item["id"]), Some(&customer), None).await;
+    assert_eq!(item["quantity"], 5);
+}
+
+#[tokio::test]
+async fn checkout_rejects_totals_too_large_to_store() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Yacht", "999999999999999.99", 2).await;
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/orders/checkout",
+        Some(&customer),
+        Some(json!({ "lines": [{ "item_id": item["id"], "quantity": 2 }] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["lines"]);
+}
+
+#[tokio::test]
+async fn cart_rejects_overflowing_quantities() {
+    let app = app();
+    let seller = login_as(&app, "sam", "seller").await;
+    let customer = login_as(&app, "carol", "customer").await;
+
+    let item = create_item(&app, &seller, "Milk", "1.49", 5).await;
+    let add = |quantity: i32| {
+        send(
+            &app,
+            Method::POST,
+            "/cart/items",
+            Some(&customer),
+            Some(json!({ "item_id": item["id"], "quantity": quantity })),
+        )
+    };
+
+    let (status, _) = add(1).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = add(i32::MAX).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error_fields(&body), ["quantity"]);
+
+    let (_, cart) = send(&app, Method::GET, "/cart", Some(&customer), None).await;
+    assert_eq!(cart["lines"][0]["quantity"], 1);
+}
+
+#[tokio::test]
+async fn probes_need_no_token() {
+    let app = app();
+
+    let (status, body) = send(&app, Method::GET, "/healthz", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+
+    let (status, body) = send(&app, Method::GET, "/readyz", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["migrations"]["pending"], 0);
+
+    let (status, body) = send(&app, Method::GET, "/version", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn metrics_count_routes_by_template_and_logins() {
+    let app = app();
+    let token = login_as(&app, "seller", "seller").await;
+    let item = create_item(&app, &token, "Lamp", "3.00", 2).await;
+    let id = item["id"].as_i64().unwrap();
+
+    send(&app, Method::GET, &format!("/items/{}", id), Some(&token), None).await;
+    send(&app, Method::GET, "/items/999999", Some(&token), None).await;
+    send(
+        &app,
+        Method::POST,
+        "/auth/login",
+        None,
+        Some(json!({ "username": "seller", "password": "wrong" })),
+    )
+    .await;
+
+    let (status, body) = send(&app, Method::GET, "/metrics", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let text = body.as_str().unwrap();
+
+    assert!(text.contains(r#"http_requests_total{method="GET",route="/items/:id",status="2xx"} 1"#));
+    assert!(text.contains(r#"http_requests_total{method="GET",route="/items/:id",status="4xx"} 1"#));
+    assert!(!text.contains("/items/999999"));
+    assert!(text.contains(r#"auth_logins_total{result="success"} 1"#));
+    assert!(text.contains(r#"auth_logins_total{result="failure"} 1"#));
+    assert!(text.contains(&format!(r#"store_item_stock{{item_id="{}",name="Lamp"}} 2"#, id)));
+    assert!(text.contains("store_low_stock_items 1"));
+}
+
+#[tokio::test]
+async fn request_id_is_echoed_or_generated() {
+    let app = app();
+
+    let request = Request::builder()
+        .uri("/healthz")
+        .header("x-request-id", "abc-123")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+    // Missing or unusable ids are replaced.
+    for header in [None, Some("has spaces"), Some("")] {
+        let mut request = Request::builder().uri("/nowhere");
+        if let Some(value) = header {
+            request = request.header("x-request-id", value);
+        }
+
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(id.len(), 32, "{:?}", header);
+    }
+}
+
+/// `(method, path)` of every `.route(...)` in `create_routes`, with axum's
+/// `:param` segments written the OpenAPI way.
+fn declared_routes() -> Vec<(String, String)> {
+    include_str!("../src/routes.rs")
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix(".route(\"")?;
+            let (path, rest) = rest.split_once('"')?;
+            let (method, _) = rest.trim_start_matches([',', ' ']).split_once('(')?;
+
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => segment.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            Some((method.to_owned(), path))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn openapi_documents_every_route() {
+    let app = app();
+
+    let (status, spec) = send(&app, Method::GET, "/openapi.json", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+
+    let routes = declared_routes();
+    assert!(routes.len() > 30, "routes.rs was not parsed: {:?}", routes);
+
+    let missing: Vec<_> = routes
+        .iter()
+        .filter(|(method, path)| spec["paths"][path][method].is_null())
+        .collect();
+    assert!(missing.is_empty(), "routes missing from the spec: {:?}", missing);
+
+    for schema in ["Item", "Money", "OrderDetail", "Problem"] {
+        assert!(spec["components"]["schemas"][schema].is_object(), "no {} schema", schema);
+    }
+
+    // Secured operations point at a scheme that exists.
+    assert_eq!(spec["components"]["securitySchemes"]["bearer"]["scheme"], "bearer");
+    assert!(spec["paths"]["/items/{id}"]["get"]["security"].is_array());
+    assert!(spec["paths"]["/auth/login"]["post"]["security"].is_null());
+}
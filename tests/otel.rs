@@ -0,0 +1,81 @@
+//! Trace export against a stand-in OTLP collector. Kept in its own test
+//! binary because it installs the global subscriber.
+
+use axum::{
+    body::{Body, Bytes},
+    http::Request,
+    routing::post,
+    Router,
+};
+use serde_json::json;
+use store::{
+    config::{Config, LogConfig, OtelConfig},
+    routes::create_routes,
+    state::AppState,
+    telemetry,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+use tower::ServiceExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+/// Accepts OTLP/HTTP exports and hands their bodies to the test.
+async fn collector() -> (String, mpsc::UnboundedReceiver<Bytes>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let app = Router::new().route(
+        "/v1/traces",
+        post(move |body: Bytes| async move {
+            tx.send(body).ok();
+        }),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    (endpoint, rx)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_and_continue_the_incoming_trace() {
+    let (endpoint, mut exports) = collector().await;
+
+    let otel = OtelConfig {
+        enabled: true,
+        endpoint,
+        ..OtelConfig::default()
+    };
+    let guard = telemetry::init(&LogConfig::default(), &otel).unwrap();
+
+    let mut config = Config::default();
+    config.auth.jwt_secret = "test-secret-that-is-long-enough-for-hs256".into();
+    config.auth.bcrypt_cost = 4;
+    let app = create_routes(AppState::in_memory(config));
+
+    let request = Request::post("/auth/register")
+        .header("content-type", "application/json")
+        .header("traceparent", format!("00-{}-00f067aa0ba902b7-01", TRACE_ID))
+        .body(Body::from(
+            json!({ "username": "traced", "password": "hunter22", "role": "customer" }).to_string(),
+        ))
+        .unwrap();
+    app.oneshot(request).await.unwrap();
+
+    // Shutting down flushes the batch; it blocks, so keep it off the runtime.
+    tokio::task::spawn_blocking(move || drop(guard)).await.unwrap();
+
+    let mut exported = Vec::new();
+    while let Ok(body) = exports.try_recv() {
+        exported.extend_from_slice(&body);
+    }
+
+    assert!(!exported.is_empty(), "nothing was exported");
+    assert!(contains(&exported, b"POST /auth/register"));
+    assert!(contains(&exported, b"register_user"));
+    assert!(contains(&exported, &hex::decode(TRACE_ID).unwrap()));
+}
@@ -0,0 +1,111 @@
+//! Fields recorded on the request span, captured by a test layer.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use store::{config::Config, routes::create_routes, state::AppState};
+use tower::ServiceExt;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// `(span name, value)` of every `user` field recorded on a span.
+#[derive(Clone, Default)]
+struct UserFields(Arc<Mutex<Vec<(String, String)>>>);
+
+struct UserVisitor<'a>(&'a mut Option<String>);
+
+impl Visit for UserVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "user" {
+            *self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "user" {
+            *self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+impl UserFields {
+    fn push<S>(&self, id: &Id, ctx: &Context<'_, S>, user: Option<String>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if let (Some(user), Some(span)) = (user, ctx.span(id)) {
+            self.0.lock().unwrap().push((span.name().to_string(), user));
+        }
+    }
+}
+
+impl<S> Layer<S> for UserFields
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut user = None;
+        attrs.record(&mut UserVisitor(&mut user));
+        self.push(id, &ctx, user);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut user = None;
+        values.record(&mut UserVisitor(&mut user));
+        self.push(id, &ctx, user);
+    }
+}
+
+async fn post(app: &Router, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+    let mut request = Request::post(uri).header(header::CONTENT_TYPE, "application/json");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let request = request.body(Body::from(body.to_string())).unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+    (status, body)
+}
+
+#[tokio::test]
+async fn authenticated_user_is_recorded_on_the_request_span() {
+    let fields = UserFields::default();
+    // The test runs on a single thread, so a thread-local subscriber sees
+    // every span of the request.
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(fields.clone()));
+
+    let mut config = Config::default();
+    config.auth.jwt_secret = "test-secret-that-is-long-enough-for-hs256".into();
+    config.auth.bcrypt_cost = 4;
+    let app = create_routes(AppState::in_memory(config));
+
+    let credentials = json!({ "username": "alice", "password": "hunter22", "role": "customer" });
+    let (status, _) = post(&app, "/auth/register", None, credentials.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = post(&app, "/auth/login", None, credentials).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(fields.0.lock().unwrap().is_empty());
+
+    let token = body["access_token"].as_str().unwrap();
+    let (status, _) = post(&app, "/auth/logout", Some(token), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(
+        *fields.0.lock().unwrap(),
+        vec![("request".to_string(), "alice".to_string())]
+    );
+}